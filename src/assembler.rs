@@ -0,0 +1,1210 @@
+//! Compiles Octo source, which is also what `disassembler` writes, so its
+//! output assembles back to the same bytes.
+//!
+//! ```text
+//! # Comments run to the end of the line
+//! :const SPEED 2              # Named number, defined before use
+//! :calc HALF { SPEED / 2 }    # Evaluated right to left, no precedence
+//! :alias x v0                 # Another name for a register
+//! :include "sprites.8o"       # Compiled in place, relative to this file
+//! :macro move reg { reg += SPEED }
+//!
+//! : main                      # Label for the next address
+//!     x := 0
+//!     i := ball
+//!     loop
+//!         move x
+//!         while x < 60        # Leaves the loop when false
+//!         if x == 30 begin
+//!             :call draw      # Or just "draw"
+//!         else
+//!             clear
+//!         end
+//!     again
+//!
+//! : draw
+//!     sprite x x 1
+//!     return
+//!
+//! :org 0x300                  # Carry on compiling at an address
+//! : ball
+//!     0x80                    # Bare numbers are bytes
+//!     :byte { HALF + 1 }
+//!     :word main              # Big-endian 16-bit value
+//! ```
+//!
+//! Statements are `clear`, `return`, `jump`, `jump0`, `:call`,
+//! `if COND then`, `if COND begin ... else ... end`,
+//! `loop ... while COND ... again`, `vX := vY|NN|random NN|delay|key`,
+//! `vX += -= =- |= &= ^= >>= <<=`, `i := ADDR|long ADDR|hex vX|bighex vX`,
+//! `i += vX`, `delay := vX`, `buzzer := vX`, `pitch := vX`,
+//! `sprite vX vY N`, `bcd vX`, `save`/`load vX` or `vX - vY`,
+//! `saveflags`/`loadflags vX`, `plane N`, `audio`, `scroll-down N`,
+//! `scroll-up N`, `scroll-left`, `scroll-right`, `lores`, `hires` and
+//! `exit`. Conditions compare a
+//! register with `==`, `!=`, `<`, `>`, `<=` or `>=` against a register or
+//! number, the last four using VF, or test `key`/`-key`. Labels may be used
+//! before they are defined.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::instruction::Instruction;
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_END: usize = 0x10000;
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// An assembled program, loaded at 0x200, and the address of every label.
+#[derive(Debug, PartialEq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+}
+
+impl Assembly {
+    /// One `name 0xADDR` line per label, in address order.
+    pub fn symbol_map(&self) -> String {
+        let mut labels: Vec<(&String, &u16)> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, addr)| (*addr, name.clone()));
+
+        labels
+            .iter()
+            .map(|(name, addr)| format!("{} {:#06x}\n", name, addr))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+/// Splits source into whitespace separated tokens, keeping quoted strings
+/// whole and dropping comments.
+fn tokenize(source: &str) -> Result<Vec<Token>, (usize, usize, String)> {
+    let mut tokens = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            if chars[i].is_whitespace() {
+                i += 1;
+                continue;
+            }
+            if chars[i] == '#' {
+                break;
+            }
+
+            let start = i;
+            if chars[i] == '"' {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err((
+                        line_index + 1,
+                        start + 1,
+                        String::from("unterminated string"),
+                    ));
+                }
+                i += 1;
+            } else {
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    i += 1;
+                }
+            }
+
+            tokens.push(Token {
+                text: chars[start..i].iter().collect(),
+                line: line_index + 1,
+                column: start + 1,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+
+    usize::from_str_radix(digit, 16).ok()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        && parse_register(text).is_none()
+}
+
+/// Where a label's address goes once it is known.
+enum FixupKind {
+    /// The low 12 bits of the instruction word at the offset.
+    Addr12,
+    /// The whole 16-bit word at the offset.
+    Word,
+}
+
+struct Fixup {
+    offset: usize,
+    kind: FixupKind,
+    label: String,
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A structured control flow block waiting for its closing word.
+enum Block {
+    /// The jump past the body of `if ... begin`, patched at `else` or `end`.
+    If { jump: usize },
+    /// The jump past the `else` body, patched at `end`.
+    Else { jump: usize },
+    /// Where `again` jumps back to, and the jumps out of each `while`.
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+struct OpenBlock {
+    block: Block,
+    file: String,
+    token: Token,
+}
+
+/// The instructions testing a condition, ending in one that skips the next
+/// instruction when the condition is false.
+struct Condition {
+    setup: Vec<Instruction>,
+    skip: Instruction,
+}
+
+impl Condition {
+    /// The same test, skipping when the condition is true instead.
+    fn inverted(self) -> Condition {
+        let skip = match self.skip {
+            Instruction::SeByte { x, nn } => Instruction::SneByte { x, nn },
+            Instruction::SneByte { x, nn } => Instruction::SeByte { x, nn },
+            Instruction::SeRegReg { x, y } => Instruction::SneRegReg { x, y },
+            Instruction::SneRegReg { x, y } => Instruction::SeRegReg { x, y },
+            Instruction::Skp { x } => Instruction::Sknp { x },
+            Instruction::Sknp { x } => Instruction::Skp { x },
+            other => other,
+        };
+
+        Condition {
+            setup: self.setup,
+            skip,
+        }
+    }
+}
+
+struct Assembler {
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    macro_expansions: usize,
+    blocks: Vec<OpenBlock>,
+    fixups: Vec<Fixup>,
+    include_stack: Vec<PathBuf>,
+}
+
+/// Tokens of one source file being assembled.
+struct Source {
+    file: String,
+    dir: PathBuf,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Source {
+    fn error(&self, token: &Token, message: String) -> AssembleError {
+        AssembleError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message,
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let (line, column) = self
+                    .tokens
+                    .last()
+                    .map(|token| (token.line, token.column + token.text.len()))
+                    .unwrap_or((1, 1));
+                Err(AssembleError {
+                    file: self.file.clone(),
+                    line,
+                    column,
+                    message: String::from("unexpected end of file"),
+                })
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(
+                &token,
+                format!("expected '{}', found '{}'", text, token.text),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Tokens up to the `}` matching an already read `{`.
+    fn braced(&mut self) -> Result<Vec<Token>, AssembleError> {
+        let mut tokens = Vec::new();
+        let mut depth = 1;
+
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(tokens);
+                    }
+                }
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+}
+
+impl Assembler {
+    fn offset(&self) -> usize {
+        self.here - PROGRAM_START
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) {
+        let offset = self.offset();
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.emit_bytes(&instruction.encode().to_be_bytes());
+    }
+
+    /// Emits a jump whose address is filled in later by `patch_jump`.
+    fn emit_jump_placeholder(&mut self) -> usize {
+        let offset = self.offset();
+        self.emit(Instruction::Jmp { addr: 0 });
+        offset
+    }
+
+    fn patch_jump(
+        &mut self,
+        source: &Source,
+        token: &Token,
+        offset: usize,
+    ) -> Result<(), AssembleError> {
+        if self.here > 0xFFF {
+            return Err(source.error(
+                token,
+                format!("jump target {:#x} is out of range", self.here),
+            ));
+        }
+        self.rom[offset] = 0x10 | (self.here >> 8) as u8;
+        self.rom[offset + 1] = self.here as u8;
+        Ok(())
+    }
+
+    fn define(&self, source: &Source, name: &Token) -> Result<(), AssembleError> {
+        if !is_identifier(&name.text) {
+            return Err(source.error(name, format!("invalid name '{}'", name.text)));
+        }
+        if self.labels.contains_key(&name.text)
+            || self.constants.contains_key(&name.text)
+            || self.aliases.contains_key(&name.text)
+            || self.macros.contains_key(&name.text)
+        {
+            return Err(source.error(name, format!("'{}' is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn register_of(&self, text: &str) -> Option<usize> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register(&self, source: &mut Source) -> Result<usize, AssembleError> {
+        let token = source.next()?;
+        self.register_of(&token.text).ok_or_else(|| {
+            source.error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    /// Value of a number, constant or already defined label.
+    fn known_value(&self, text: &str) -> Option<f64> {
+        parse_number(text)
+            .map(|value| value as f64)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&addr| addr as f64))
+    }
+
+    /// A number, constant or label that must fit in `min..=max`.
+    fn value(&self, source: &mut Source, min: i64, max: i64) -> Result<i64, AssembleError> {
+        let token = source.next()?;
+        let value = self
+            .known_value(&token.text)
+            .ok_or_else(|| {
+                source.error(&token, format!("expected a number, found '{}'", token.text))
+            })?
+            .floor() as i64;
+
+        if !(min..=max).contains(&value) {
+            return Err(source.error(
+                &token,
+                format!("{} is out of range {}..={}", value, min, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, source: &mut Source) -> Result<u8, AssembleError> {
+        Ok(self.value(source, -128, 255)? as u8)
+    }
+
+    fn nibble(&self, source: &mut Source) -> Result<u8, AssembleError> {
+        Ok(self.value(source, 0, 15)? as u8)
+    }
+
+    /// A number, constant or label, which may not be defined yet. The
+    /// label's address is patched in at `offset` once it is known.
+    fn address_operand(
+        &mut self,
+        source: &mut Source,
+        kind: FixupKind,
+        offset: usize,
+    ) -> Result<u16, AssembleError> {
+        let token = source.next()?;
+        let max = match kind {
+            FixupKind::Addr12 => 0xFFF,
+            FixupKind::Word => 0xFFFF,
+        };
+        let known = parse_number(&token.text).or_else(|| {
+            self.constants
+                .get(&token.text)
+                .map(|value| value.floor() as i64)
+        });
+
+        match known {
+            Some(value) if (0..=max).contains(&value) => Ok(value as u16),
+            Some(value) => {
+                Err(source.error(&token, format!("address {:#x} is out of range", value)))
+            }
+            None if is_identifier(&token.text) => {
+                self.fixups.push(Fixup {
+                    offset,
+                    kind,
+                    label: token.text.clone(),
+                    file: source.file.clone(),
+                    line: token.line,
+                    column: token.column,
+                });
+                Ok(0)
+            }
+            None => Err(source.error(
+                &token,
+                format!("expected an address, found '{}'", token.text),
+            )),
+        }
+    }
+
+    fn assemble_source(&mut self, source: &mut Source) -> Result<(), AssembleError> {
+        while source.pos < source.tokens.len() {
+            self.statement(source)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, source: &mut Source) -> Result<(), AssembleError> {
+        let token = source.next()?;
+
+        match token.text.as_str() {
+            "clear" => self.emit(Instruction::Cls),
+            "return" | ";" => self.emit(Instruction::Ret),
+            "exit" => self.emit(Instruction::Exit),
+            "lores" => self.emit(Instruction::LowRes),
+            "hires" => self.emit(Instruction::HighRes),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "audio" => self.emit(Instruction::LdAudioPattern),
+            "scroll-down" => {
+                let n = self.nibble(source)?;
+                self.emit(Instruction::ScrollDown { n });
+            }
+            "scroll-up" => {
+                let n = self.nibble(source)?;
+                self.emit(Instruction::ScrollUp { n });
+            }
+            "plane" => {
+                let x = self.value(source, 0, 3)? as usize;
+                self.emit(Instruction::SelectPlanes { x });
+            }
+            "jump" => {
+                let addr = self.address_operand(source, FixupKind::Addr12, self.offset())?;
+                self.emit(Instruction::Jmp { addr });
+            }
+            "jump0" => {
+                let addr = self.address_operand(source, FixupKind::Addr12, self.offset())?;
+                self.emit(Instruction::JmpToAddrReg0 { addr });
+            }
+            ":call" => {
+                let addr = self.address_operand(source, FixupKind::Addr12, self.offset())?;
+                self.emit(Instruction::Call { addr });
+            }
+            "sprite" => {
+                let x = self.register(source)?;
+                let y = self.register(source)?;
+                let n = self.nibble(source)?;
+                self.emit(Instruction::Draw { x, y, n });
+            }
+            "bcd" => {
+                let x = self.register(source)?;
+                self.emit(Instruction::Bcd { x });
+            }
+            "saveflags" => {
+                let x = self.register(source)?;
+                self.emit(Instruction::LdRegToRpl { x });
+            }
+            "loadflags" => {
+                let x = self.register(source)?;
+                self.emit(Instruction::LdRplToReg { x });
+            }
+            "save" | "load" => {
+                let x = self.register(source)?;
+                let save = token.text == "save";
+                if source.peek() == Some("-") {
+                    source.next()?;
+                    let y = self.register(source)?;
+                    self.emit(if save {
+                        Instruction::LdRangeToRam { x, y }
+                    } else {
+                        Instruction::LdRamToRange { x, y }
+                    });
+                } else {
+                    self.emit(if save {
+                        Instruction::LdRegToRam { x }
+                    } else {
+                        Instruction::LdRamToReg { x }
+                    });
+                }
+            }
+            "if" => self.if_statement(source, &token)?,
+            "else" => match self.blocks.pop() {
+                Some(OpenBlock {
+                    block: Block::If { jump },
+                    ..
+                }) => {
+                    let end_jump = self.emit_jump_placeholder();
+                    self.patch_jump(source, &token, jump)?;
+                    self.blocks.push(OpenBlock {
+                        block: Block::Else { jump: end_jump },
+                        file: source.file.clone(),
+                        token: token.clone(),
+                    });
+                }
+                _ => {
+                    return Err(source.error(&token, String::from("'else' without 'if ... begin'")))
+                }
+            },
+            "end" => match self.blocks.pop() {
+                Some(OpenBlock {
+                    block: Block::If { jump } | Block::Else { jump },
+                    ..
+                }) => self.patch_jump(source, &token, jump)?,
+                _ => return Err(source.error(&token, String::from("'end' without 'if ... begin'"))),
+            },
+            "loop" => self.blocks.push(OpenBlock {
+                block: Block::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                },
+                file: source.file.clone(),
+                token: token.clone(),
+            }),
+            "while" => {
+                let condition = self.condition(source)?.inverted();
+                self.emit_condition(condition);
+                let jump = self.emit_jump_placeholder();
+
+                let exits = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find_map(|open| match &mut open.block {
+                        Block::Loop { exits, .. } => Some(exits),
+                        _ => None,
+                    });
+                match exits {
+                    Some(exits) => exits.push(jump),
+                    None => {
+                        return Err(source.error(&token, String::from("'while' outside a loop")))
+                    }
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(OpenBlock {
+                    block: Block::Loop { start, exits },
+                    ..
+                }) => {
+                    self.emit(Instruction::Jmp { addr: start as u16 });
+                    if start > 0xFFF {
+                        return Err(source
+                            .error(&token, format!("loop start {:#x} is out of range", start)));
+                    }
+                    for jump in exits {
+                        self.patch_jump(source, &token, jump)?;
+                    }
+                }
+                _ => return Err(source.error(&token, String::from("'again' without 'loop'"))),
+            },
+            "i" => self.index_statement(source)?,
+            "delay" | "buzzer" | "pitch" => {
+                source.expect(":=")?;
+                let x = self.register(source)?;
+                self.emit(match token.text.as_str() {
+                    "delay" => Instruction::LdRegToDt { x },
+                    "buzzer" => Instruction::LdStToReg { x },
+                    _ => Instruction::LdPitch { x },
+                });
+            }
+            ":" => {
+                let name = source.next()?;
+                self.define(source, &name)?;
+                self.labels.insert(name.text, self.here as u16);
+            }
+            ":const" => {
+                let name = source.next()?;
+                self.define(source, &name)?;
+                let value = self.value(source, i64::MIN, i64::MAX)?;
+                self.constants.insert(name.text, value as f64);
+            }
+            ":calc" => {
+                let name = source.next()?;
+                self.define(source, &name)?;
+                source.expect("{")?;
+                let value = self.calc(source)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = source.next()?;
+                self.define(source, &name)?;
+                let x = self.register(source)?;
+                self.aliases.insert(name.text, x);
+            }
+            ":macro" => {
+                let name = source.next()?;
+                self.define(source, &name)?;
+                let mut params = Vec::new();
+                loop {
+                    let param = source.next()?;
+                    if param.text == "{" {
+                        break;
+                    }
+                    params.push(param.text);
+                }
+                let body = source.braced()?;
+                self.macros.insert(name.text, Macro { params, body });
+            }
+            ":org" => {
+                let addr = self.value(source, PROGRAM_START as i64, MEMORY_END as i64 - 1)?;
+                self.here = addr as usize;
+            }
+            ":byte" => {
+                let value = if source.peek() == Some("{") {
+                    source.next()?;
+                    let value = self.calc(source)?.floor() as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(
+                            source.error(&token, format!("{} is out of range -128..=255", value))
+                        );
+                    }
+                    value as u8
+                } else {
+                    self.byte(source)?
+                };
+                self.emit_bytes(&[value]);
+            }
+            ":word" => {
+                let value = self.address_operand(source, FixupKind::Word, self.offset())?;
+                self.emit_bytes(&value.to_be_bytes());
+            }
+            ":include" => self.include(source, &token)?,
+            text => {
+                if let Some(x) = self.register_of(text) {
+                    self.register_statement(source, x)?;
+                } else if self.macros.contains_key(text) {
+                    self.expand_macro(source, &token)?;
+                } else if self.known_value(text).is_some() && !self.labels.contains_key(text) {
+                    source.pos -= 1;
+                    let value = self.byte(source)?;
+                    self.emit_bytes(&[value]);
+                } else if is_identifier(text) {
+                    // A bare label name calls it
+                    source.pos -= 1;
+                    let addr = self.address_operand(source, FixupKind::Addr12, self.offset())?;
+                    self.emit(Instruction::Call { addr });
+                } else {
+                    return Err(source.error(&token, format!("unknown statement '{}'", text)));
+                }
+            }
+        }
+
+        if self.here > MEMORY_END {
+            return Err(source.error(&token, String::from("program does not fit in memory")));
+        }
+        Ok(())
+    }
+
+    /// Replaces a macro call with the macro's body, its parameters swapped
+    /// for the arguments that follow the call.
+    fn expand_macro(&mut self, source: &mut Source, call: &Token) -> Result<(), AssembleError> {
+        self.macro_expansions += 1;
+        if self.macro_expansions > MAX_MACRO_EXPANSIONS {
+            return Err(source.error(call, String::from("too many macro expansions")));
+        }
+
+        let mac = &self.macros[&call.text];
+        let mut args = HashMap::new();
+        for param in mac.params.iter() {
+            args.insert(param.clone(), source.next()?);
+        }
+
+        let body: Vec<Token> = mac
+            .body
+            .iter()
+            .map(|token| args.get(&token.text).unwrap_or(token).clone())
+            .collect();
+        source.tokens.splice(source.pos..source.pos, body);
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression up to its closing `}`.
+    fn calc(&self, source: &mut Source) -> Result<f64, AssembleError> {
+        let tokens = source.braced()?;
+        let mut pos = 0;
+        let value = self.calc_expression(source, &tokens, &mut pos)?;
+
+        match tokens.get(pos) {
+            Some(token) => Err(source.error(token, format!("unexpected '{}'", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Like Octo, binary operators have no precedence and group to the
+    /// right, so `2 * 3 + 1` is 8.
+    fn calc_expression(
+        &self,
+        source: &Source,
+        tokens: &[Token],
+        pos: &mut usize,
+    ) -> Result<f64, AssembleError> {
+        let lhs = self.calc_term(source, tokens, pos)?;
+        let op = match tokens.get(*pos) {
+            Some(op) if op.text != ")" => op.clone(),
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.calc_expression(source, tokens, pos)?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let shifted = |shift: fn(i64, u32) -> Option<i64>| {
+            u32::try_from(b)
+                .ok()
+                .and_then(|b| shift(a, b))
+                .map(|value| value as f64)
+                .ok_or_else(|| source.error(&op, format!("cannot shift by {}", b)))
+        };
+        if (op.text == "/" || op.text == "%") && rhs == 0.0 {
+            return Err(source.error(&op, String::from("division by zero")));
+        }
+
+        let value = match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => shifted(i64::checked_shl)?,
+            ">>" => shifted(i64::checked_shr)?,
+            "<" => (lhs < rhs) as i64 as f64,
+            ">" => (lhs > rhs) as i64 as f64,
+            "<=" => (lhs <= rhs) as i64 as f64,
+            ">=" => (lhs >= rhs) as i64 as f64,
+            "==" => (lhs == rhs) as i64 as f64,
+            "!=" => (lhs != rhs) as i64 as f64,
+            _ => return Err(source.error(&op, format!("unknown operator '{}'", op.text))),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(
+        &self,
+        source: &Source,
+        tokens: &[Token],
+        pos: &mut usize,
+    ) -> Result<f64, AssembleError> {
+        let token = match tokens.get(*pos) {
+            Some(token) => token,
+            None => {
+                let last = tokens.last().cloned().unwrap_or(Token {
+                    text: String::new(),
+                    line: 0,
+                    column: 0,
+                });
+                return Err(source.error(&last, String::from("expected a value")));
+            }
+        };
+        *pos += 1;
+
+        let unary = |f: fn(f64) -> f64, pos: &mut usize| -> Result<f64, AssembleError> {
+            Ok(f(self.calc_term(source, tokens, pos)?))
+        };
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(source, tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(source.error(token, String::from("missing ')'"))),
+                }
+            }
+            "-" => unary(|x| -x, pos),
+            "~" => unary(|x| !(x as i64) as f64, pos),
+            "!" => unary(|x| (x == 0.0) as i64 as f64, pos),
+            "sin" => unary(f64::sin, pos),
+            "cos" => unary(f64::cos, pos),
+            "tan" => unary(f64::tan, pos),
+            "exp" => unary(f64::exp, pos),
+            "log" => unary(f64::ln, pos),
+            "abs" => unary(f64::abs, pos),
+            "sqrt" => unary(f64::sqrt, pos),
+            "sign" => unary(f64::signum, pos),
+            "ceil" => unary(f64::ceil, pos),
+            "floor" => unary(f64::floor, pos),
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => self
+                .known_value(text)
+                .or_else(|| text.parse().ok())
+                .ok_or_else(|| source.error(token, format!("undefined name '{}'", text))),
+        }
+    }
+
+    fn if_statement(&mut self, source: &mut Source, token: &Token) -> Result<(), AssembleError> {
+        let condition = self.condition(source)?;
+        let word = source.next()?;
+
+        match word.text.as_str() {
+            "then" => self.emit_condition(condition),
+            "begin" => {
+                self.emit_condition(condition.inverted());
+                let jump = self.emit_jump_placeholder();
+                self.blocks.push(OpenBlock {
+                    block: Block::If { jump },
+                    file: source.file.clone(),
+                    token: token.clone(),
+                });
+            }
+            _ => {
+                return Err(source.error(
+                    &word,
+                    format!("expected 'then' or 'begin', found '{}'", word.text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_condition(&mut self, condition: Condition) {
+        for instruction in condition.setup {
+            self.emit(instruction);
+        }
+        self.emit(condition.skip);
+    }
+
+    fn condition(&mut self, source: &mut Source) -> Result<Condition, AssembleError> {
+        let x = self.register(source)?;
+        let op = source.next()?;
+
+        let skip = |skip| {
+            Ok(Condition {
+                setup: Vec::new(),
+                skip,
+            })
+        };
+
+        match op.text.as_str() {
+            "key" => return skip(Instruction::Sknp { x }),
+            "-key" => return skip(Instruction::Skp { x }),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            _ => {
+                return Err(source.error(
+                    &op,
+                    format!(
+                        "expected a comparison, 'key' or '-key', found '{}'",
+                        op.text
+                    ),
+                ))
+            }
+        }
+
+        let y = source.peek().and_then(|text| self.register_of(text));
+        let nn = match y {
+            Some(_) => {
+                source.next()?;
+                0
+            }
+            None => self.byte(source)?,
+        };
+
+        match (op.text.as_str(), y) {
+            ("==", Some(y)) => skip(Instruction::SneRegReg { x, y }),
+            ("==", None) => skip(Instruction::SneByte { x, nn }),
+            ("!=", Some(y)) => skip(Instruction::SeRegReg { x, y }),
+            ("!=", None) => skip(Instruction::SeByte { x, nn }),
+            (op, y) => {
+                // VF is left 1 by the subtraction when there is no borrow,
+                // that is when the first operand is at least the second
+                let (x_at_least_y, flag) = match op {
+                    "<" => (true, 0),
+                    ">=" => (true, 1),
+                    ">" => (false, 0),
+                    _ => (false, 1),
+                };
+                let setup = match (x_at_least_y, y) {
+                    (true, Some(y)) => vec![
+                        Instruction::LdRegReg { x: 0xF, y: x },
+                        Instruction::SubRegReg { x: 0xF, y },
+                    ],
+                    (true, None) => vec![
+                        Instruction::SetRegToNn { x: 0xF, nn },
+                        Instruction::SubNotBorrow { x: 0xF, y: x },
+                    ],
+                    (false, Some(y)) => vec![
+                        Instruction::LdRegReg { x: 0xF, y },
+                        Instruction::SubRegReg { x: 0xF, y: x },
+                    ],
+                    (false, None) => vec![
+                        Instruction::SetRegToNn { x: 0xF, nn },
+                        Instruction::SubRegReg { x: 0xF, y: x },
+                    ],
+                };
+
+                Ok(Condition {
+                    setup,
+                    skip: Instruction::SneByte { x: 0xF, nn: flag },
+                })
+            }
+        }
+    }
+
+    fn index_statement(&mut self, source: &mut Source) -> Result<(), AssembleError> {
+        let op = source.next()?;
+
+        match op.text.as_str() {
+            ":=" => match source.peek() {
+                Some("long") => {
+                    source.next()?;
+                    let addr = self.address_operand(source, FixupKind::Word, self.offset() + 2)?;
+                    self.emit(Instruction::LdLongIndex);
+                    self.emit_bytes(&addr.to_be_bytes());
+                }
+                Some("hex") => {
+                    source.next()?;
+                    let x = self.register(source)?;
+                    self.emit(Instruction::LdFont { x });
+                }
+                Some("bighex") => {
+                    source.next()?;
+                    let x = self.register(source)?;
+                    self.emit(Instruction::LdBigFont { x });
+                }
+                _ => {
+                    let addr = self.address_operand(source, FixupKind::Addr12, self.offset())?;
+                    self.emit(Instruction::SetIndexRegToAddr { addr });
+                }
+            },
+            "+=" => {
+                let x = self.register(source)?;
+                self.emit(Instruction::AddIToReg { x });
+            }
+            _ => {
+                return Err(source.error(&op, format!("expected ':=' or '+=', found '{}'", op.text)))
+            }
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, source: &mut Source, x: usize) -> Result<(), AssembleError> {
+        let op = source.next()?;
+        let y = source.peek().and_then(|text| self.register_of(text));
+
+        let instruction = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::LdRegReg { x, y },
+            (":=", None) => match source.peek() {
+                Some("random") => {
+                    source.next()?;
+                    let nn = self.byte(source)?;
+                    self.emit(Instruction::RndNum { x, nn });
+                    return Ok(());
+                }
+                Some("delay") => Instruction::LdDtToReg { x },
+                Some("key") => Instruction::LdKey { x },
+                _ => {
+                    let nn = self.byte(source)?;
+                    self.emit(Instruction::SetRegToNn { x, nn });
+                    return Ok(());
+                }
+            },
+            ("+=", Some(y)) => Instruction::AddRegReg { x, y },
+            ("+=", None) => {
+                let nn = self.byte(source)?;
+                self.emit(Instruction::AddValToReg { x, nn });
+                return Ok(());
+            }
+            ("-=", Some(y)) => Instruction::SubRegReg { x, y },
+            ("-=", None) => {
+                let nn = self.byte(source)?.wrapping_neg();
+                self.emit(Instruction::AddValToReg { x, nn });
+                return Ok(());
+            }
+            ("=-", Some(y)) => Instruction::SubNotBorrow { x, y },
+            ("|=", Some(y)) => Instruction::BitOr { x, y },
+            ("&=", Some(y)) => Instruction::BitAnd { x, y },
+            ("^=", Some(y)) => Instruction::BitXor { x, y },
+            (">>=", Some(y)) => Instruction::Shr { x, y },
+            ("<<=", Some(y)) => Instruction::Shl { x, y },
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                let token = source.next()?;
+                return Err(source.error(
+                    &token,
+                    format!("expected a register, found '{}'", token.text),
+                ));
+            }
+            _ => return Err(source.error(&op, format!("unknown operator '{}'", op.text))),
+        };
+
+        // The operand was only peeked at
+        source.next()?;
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn include(&mut self, source: &mut Source, directive: &Token) -> Result<(), AssembleError> {
+        let token = source.next()?;
+        let name = token
+            .text
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .ok_or_else(|| source.error(&token, String::from("expected a quoted file name")))?;
+        let path = source.dir.join(name);
+
+        if self.include_stack.len() >= MAX_INCLUDE_DEPTH || self.include_stack.contains(&path) {
+            return Err(source.error(
+                directive,
+                format!("{} is included recursively", path.display()),
+            ));
+        }
+
+        let text = std::fs::read_to_string(&path).map_err(|err| {
+            source.error(
+                &token,
+                format!("could not read {}: {}", path.display(), err),
+            )
+        })?;
+        self.include_stack.push(path.clone());
+        self.assemble_text(&text, &path)?;
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    fn assemble_text(&mut self, text: &str, path: &Path) -> Result<(), AssembleError> {
+        let file = path.display().to_string();
+        let tokens = tokenize(text).map_err(|(line, column, message)| AssembleError {
+            file: file.clone(),
+            line,
+            column,
+            message,
+        })?;
+
+        let mut source = Source {
+            file,
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            tokens,
+            pos: 0,
+        };
+        self.assemble_source(&mut source)
+    }
+
+    fn resolve_fixups(&mut self) -> Result<(), AssembleError> {
+        for fixup in self.fixups.iter() {
+            let error = |message: String| AssembleError {
+                file: fixup.file.clone(),
+                line: fixup.line,
+                column: fixup.column,
+                message,
+            };
+            let addr = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| error(format!("undefined label '{}'", fixup.label)))?;
+
+            match fixup.kind {
+                FixupKind::Addr12 => {
+                    if addr > 0xFFF {
+                        return Err(error(format!(
+                            "'{}' at {:#x} is out of range, use 'i := long'",
+                            fixup.label, addr
+                        )));
+                    }
+                    self.rom[fixup.offset] |= (addr >> 8) as u8;
+                    self.rom[fixup.offset + 1] = addr as u8;
+                }
+                FixupKind::Word => {
+                    self.rom[fixup.offset..fixup.offset + 2].copy_from_slice(&addr.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assembles source text, `path` names it in errors and is where includes
+/// are looked up from.
+pub fn assemble(text: &str, path: &Path) -> Result<Assembly, AssembleError> {
+    let mut assembler = Assembler {
+        rom: Vec::new(),
+        here: PROGRAM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        macro_expansions: 0,
+        blocks: Vec::new(),
+        fixups: Vec::new(),
+        include_stack: vec![path.to_path_buf()],
+    };
+
+    assembler.assemble_text(text, path)?;
+
+    if let Some(open) = assembler.blocks.last() {
+        let closing = match open.block {
+            Block::Loop { .. } => "again",
+            _ => "end",
+        };
+        return Err(AssembleError {
+            file: open.file.clone(),
+            line: open.token.line,
+            column: open.token.column,
+            message: format!("'{}' is missing its '{}'", open.token.text, closing),
+        });
+    }
+
+    assembler.resolve_fixups()?;
+
+    Ok(Assembly {
+        rom: assembler.rom,
+        labels: assembler.labels,
+    })
+}
+
+pub fn assemble_file(path: &Path) -> Result<Assembly, AssembleError> {
+    let text = std::fs::read_to_string(path).map_err(|err| AssembleError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: format!("could not read file: {}", err),
+    })?;
+
+    assemble(&text, path)
+}
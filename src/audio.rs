@@ -0,0 +1,155 @@
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AudioSettings {
+    pub frequency: f32,
+    /// Between 0.0 and 1.0.
+    pub volume: f32,
+    pub waveform: Waveform,
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+            muted: false,
+        }
+    }
+}
+
+/// Plays the buzzer, told once per frame whether the sound timer is running.
+pub trait AudioSink {
+    fn update(&mut self, playing: bool);
+    fn toggle_mute(&mut self);
+}
+
+pub struct ToneGenerator {
+    settings: AudioSettings,
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl ToneGenerator {
+    pub fn new(settings: AudioSettings, sample_rate: u32) -> ToneGenerator {
+        ToneGenerator {
+            settings,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let value = match self.settings.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+        };
+        self.phase = (self.phase + self.settings.frequency / self.sample_rate as f32) % 1.0;
+
+        if self.settings.muted {
+            0.0
+        } else {
+            value * self.settings.volume
+        }
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.settings.muted = !self.settings.muted;
+    }
+}
+
+/// Used when there is no audio device, e.g. headless runs.
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn update(&mut self, _playing: bool) {}
+
+    fn toggle_mute(&mut self) {}
+}
+
+/// Renders every frame of audio into memory so it can be checked or saved
+/// as a WAV file.
+pub struct RecordingAudio {
+    generator: ToneGenerator,
+    pub samples: Vec<i16>,
+}
+
+impl RecordingAudio {
+    pub fn new(settings: AudioSettings) -> RecordingAudio {
+        RecordingAudio {
+            generator: ToneGenerator::new(settings, SAMPLE_RATE),
+            samples: Vec::new(),
+        }
+    }
+
+    /// 16-bit mono PCM WAV file holding everything recorded so far.
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = self.samples.len() as u32 * 2;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+        wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in self.samples.iter() {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        wav
+    }
+}
+
+impl AudioSink for RecordingAudio {
+    fn update(&mut self, playing: bool) {
+        for _ in 0..SAMPLE_RATE / 60 {
+            let sample = if playing {
+                (self.generator.next_sample() * i16::MAX as f32) as i16
+            } else {
+                0
+            };
+            self.samples.push(sample);
+        }
+    }
+
+    fn toggle_mute(&mut self) {
+        self.generator.toggle_mute();
+    }
+}
@@ -0,0 +1,162 @@
+//! The display and registers as lines of coloured text, drawn by the
+//! terminal frontend. Nothing here needs a terminal.
+
+use std::fmt::Write as _;
+
+use crate::{
+    config::Palette,
+    cpu::{Display, CPU},
+    debugger,
+};
+
+/// How display pixels are packed into character cells.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CellStyle {
+    /// `▀` with the top pixel as the foreground colour and the bottom one
+    /// as the background, 1x2 pixels per cell.
+    HalfBlocks,
+    /// Braille dots, 2x4 pixels per cell in one colour.
+    Braille,
+}
+
+/// The display as lines of text coloured with 24-bit ANSI escapes. A
+/// colour is only written where it changes along the line.
+pub fn render(
+    display: &Display,
+    (width, height): (usize, usize),
+    style: CellStyle,
+    palette: &Palette,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    match style {
+        CellStyle::HalfBlocks => {
+            for y in (0..height).step_by(2) {
+                let mut line = String::new();
+                let mut current = (None, None);
+                for (top, bottom) in display[y][..width].iter().zip(&display[y + 1][..width]) {
+                    let (top, bottom) = (top & 0b11, bottom & 0b11);
+                    // A cell in one colour needs only the background
+                    if top == bottom {
+                        set_colours(&mut line, &mut current, None, bottom, palette);
+                        line.push(' ');
+                    } else {
+                        set_colours(&mut line, &mut current, Some(top), bottom, palette);
+                        line.push('▀');
+                    }
+                }
+                line.push_str("\x1b[0m");
+                lines.push(line);
+            }
+        }
+        CellStyle::Braille => {
+            // Dot bits of the Braille block by pixel, left column first
+            const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+            for y in (0..height).step_by(4) {
+                let mut line = String::new();
+                let mut current = (None, None);
+                for x in (0..width).step_by(2) {
+                    let mut dots = 0;
+                    let mut plane = 0;
+                    for (dx, column) in DOTS.iter().enumerate() {
+                        for (dy, dot) in column.iter().enumerate() {
+                            let pixel = display[y + dy][x + dx] & 0b11;
+                            if pixel != 0 {
+                                dots |= dot;
+                                plane = plane.max(pixel);
+                            }
+                        }
+                    }
+                    let fg = (plane != 0).then_some(plane);
+                    set_colours(&mut line, &mut current, fg, 0, palette);
+                    line.push(char::from_u32(0x2800 + dots).unwrap());
+                }
+                line.push_str("\x1b[0m");
+                lines.push(line);
+            }
+        }
+    }
+
+    lines
+}
+
+/// Appends escapes for whichever of the foreground and background pixel
+/// colours differ from `current`, a foreground of `None` keeping the last.
+fn set_colours(
+    line: &mut String,
+    current: &mut (Option<u8>, Option<u8>),
+    fg: Option<u8>,
+    bg: u8,
+    palette: &Palette,
+) {
+    if let Some(pixel) = fg.filter(|&pixel| current.0 != Some(pixel)) {
+        let (r, g, b) = palette.colour(pixel);
+        write!(line, "\x1b[38;2;{};{};{}m", r, g, b).unwrap();
+        current.0 = fg;
+    }
+    if current.1 != Some(bg) {
+        let (r, g, b) = palette.colour(bg);
+        write!(line, "\x1b[48;2;{};{};{}m", r, g, b).unwrap();
+        current.1 = Some(bg);
+    }
+}
+
+/// Registers down the side of the display, then the status line if any and
+/// the latest messages.
+pub fn panel(cpu: &CPU, status: Option<&str>, messages: &[String]) -> Vec<String> {
+    let mut lines: Vec<String> = (0..8)
+        .map(|x| {
+            format!(
+                "V{:X} {:02X}  V{:X} {:02X}",
+                x,
+                cpu.regs[x],
+                x + 8,
+                cpu.regs[x + 8]
+            )
+        })
+        .collect();
+
+    lines.push(String::new());
+    lines.push(format!("I  {:04X}  SP {}", cpu.index_reg, cpu.sp));
+    lines.push(format!("DT {:<4}  ST {}", cpu.delay_timer, cpu.sound_timer));
+    lines.push(format!("PC {}", debugger::location(cpu)));
+    if let Some(status) = status {
+        lines.push(String::new());
+        lines.push(status.to_string());
+    }
+    if !messages.is_empty() {
+        lines.push(String::new());
+        lines.extend(messages.iter().cloned());
+    }
+
+    lines
+}
+
+/// The display with the panel to its right, one string per terminal row.
+pub fn screen(
+    cpu: &CPU,
+    status: Option<&str>,
+    messages: &[String],
+    style: CellStyle,
+    palette: &Palette,
+) -> Vec<String> {
+    let (width, _) = cpu.screen_size();
+    let cells = match style {
+        CellStyle::HalfBlocks => width,
+        CellStyle::Braille => width / 2,
+    };
+    let display = render(&cpu.display, cpu.screen_size(), style, palette);
+    let panel = panel(cpu, status, messages);
+
+    (0..display.len().max(panel.len()))
+        .map(|row| {
+            let left = display
+                .get(row)
+                .cloned()
+                .unwrap_or_else(|| " ".repeat(cells));
+            let right = panel.get(row).map_or("", String::as_str);
+            format!("{}  {}", left, right)
+        })
+        .collect()
+}
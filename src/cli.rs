@@ -0,0 +1,305 @@
+//! The `main` of each binary. They live in the library so the binaries can
+//! use the frontends without those being part of the public API.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    assembler::assemble_file,
+    config::{Command, Config, USAGE},
+    disassembler::{disassemble, InstructionSet},
+    headless::{self, Input, InputScript},
+    movie::{Movie, Playback},
+    storage,
+    trace::Tracer,
+    FaultMode, Machine, Quirks, Rom, StartupError,
+};
+
+#[cfg(feature = "terminal")]
+use crate::terminal;
+#[cfg(feature = "sdl")]
+use crate::windowed;
+
+/// Entry point of `rusteight`.
+pub fn rusteight() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&config) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(config: &Config) -> Result<(), StartupError> {
+    // A played movie decides everything that changes how the ROM runs
+    let movie = load_movie(config)?;
+    let mut config = config.clone();
+    if let Some(movie) = &movie {
+        config.quirks = movie.quirks;
+        config.instructions_per_frame = movie.instructions_per_frame;
+        config.seed = Some(movie.seed);
+    }
+    let config = &config;
+
+    let rom = Rom::load(&config.rom_path, config.quirks)?;
+    let rom_hash = rom.hash();
+    if let (Some(movie), Some(path)) = (&movie, &config.play_path) {
+        if movie.rom_hash != rom_hash {
+            return Err(StartupError::MovieWrongRom(path.clone()));
+        }
+    }
+
+    let mut machine = Machine::new(&rom, Quirks::preset(config.quirks));
+    machine.set_instructions_per_frame(config.instructions_per_frame);
+    machine.set_fault_mode(if config.lenient {
+        FaultMode::Lenient
+    } else {
+        FaultMode::Strict
+    });
+    machine.set_seed(config.seed.unwrap_or_else(rand::random));
+
+    let flags_path = storage::flags_path(&config.rom_path);
+    match storage::load_flags(&flags_path) {
+        Ok(flags) => machine.set_flags(flags),
+        Err(err) => eprintln!("could not read {}: {}", flags_path.display(), err),
+    }
+    if let Some(movie) = &movie {
+        machine.set_flags(movie.flags);
+    }
+    let playback = movie.map(Playback::new);
+
+    let mut tracer = if config.trace.enabled() {
+        let tracer = Tracer::open(&config.trace).map_err(|err| StartupError::TraceUnwritable {
+            path: config.trace.path.clone().unwrap_or_default(),
+            reason: err.to_string(),
+        })?;
+        Some(tracer)
+    } else {
+        None
+    };
+
+    if config.headless {
+        let mut input = match (playback, load_script(config)?) {
+            (Some(playback), _) => Input::Movie(playback),
+            (None, Some(script)) => Input::Script(script),
+            (None, None) => Input::None,
+        };
+        if let Err(fault) =
+            headless::run(&mut machine, config, &flags_path, &mut tracer, &mut input)
+        {
+            eprintln!("error: {}", fault);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "terminal")]
+    if let Some(style) = config.terminal {
+        return terminal::run(&mut machine, config, &flags_path, tracer, playback, style);
+    }
+
+    #[cfg(feature = "sdl")]
+    windowed::run(&mut machine, config, &flags_path, tracer, playback)?;
+
+    Ok(())
+}
+
+fn load_movie(config: &Config) -> Result<Option<Movie>, StartupError> {
+    let Some(path) = &config.play_path else {
+        return Ok(None);
+    };
+
+    let movie = Movie::load(Path::new(path)).map_err(|reason| StartupError::MovieUnreadable {
+        path: path.clone(),
+        reason,
+    })?;
+    Ok(Some(movie))
+}
+
+fn load_script(config: &Config) -> Result<Option<InputScript>, StartupError> {
+    let Some(path) = &config.input_path else {
+        return Ok(None);
+    };
+
+    let unreadable = |reason: String| StartupError::InputUnreadable {
+        path: path.clone(),
+        reason,
+    };
+    let text = std::fs::read_to_string(path).map_err(|err| unreadable(err.to_string()))?;
+    InputScript::parse(&text).map(Some).map_err(unreadable)
+}
+
+const ASM_USAGE: &str = "Usage: rusteight-asm [OPTIONS] <SOURCE>
+
+Arguments:
+  <SOURCE>                   Assembly source, see the assembler module for the syntax
+
+Options:
+  -o, --output <FILE>        ROM to write [default: SOURCE with a .ch8 extension]
+      --symbols <FILE>       Also write every label's address to a symbol map
+  -h, --help                 Print this help";
+
+struct AsmArgs {
+    source_path: String,
+    output: Option<String>,
+    symbols: Option<String>,
+}
+
+fn parse_asm_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<AsmArgs>, String> {
+    let mut source_path = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => {
+                output = Some(args.next().ok_or(format!("'{}' expects a value", arg))?);
+            }
+            "--symbols" => {
+                symbols = Some(args.next().ok_or(format!("'{}' expects a value", arg))?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if source_path.is_none() => source_path = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let source_path = source_path.ok_or("no source file given")?;
+    Ok(Some(AsmArgs {
+        source_path,
+        output,
+        symbols,
+    }))
+}
+
+fn write_file(path: &Path, contents: &[u8]) {
+    if let Err(err) = std::fs::write(path, contents) {
+        eprintln!("error: could not write {}: {}", path.display(), err);
+        std::process::exit(1);
+    }
+}
+
+/// Entry point of `rusteight-asm`.
+pub fn asm() {
+    let args = match parse_asm_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", ASM_USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, ASM_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let assembly = match assemble_file(Path::new(&args.source_path)) {
+        Ok(assembly) => assembly,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let output = match &args.output {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&args.source_path).with_extension("ch8"),
+    };
+    write_file(&output, &assembly.rom);
+
+    if let Some(symbols) = &args.symbols {
+        write_file(Path::new(symbols), assembly.symbol_map().as_bytes());
+    }
+}
+
+const DISASM_USAGE: &str = "Usage: rusteight-disasm [OPTIONS] <ROM>
+
+Arguments:
+  <ROM>                      CHIP-8 program to disassemble into Octo source
+
+Options:
+  -s, --set <SET>            chip8, schip or xochip [default: xochip]
+  -o, --output <FILE>        Write to a file instead of stdout
+  -h, --help                 Print this help";
+
+struct DisasmArgs {
+    rom_path: String,
+    set: InstructionSet,
+    output: Option<String>,
+}
+
+fn parse_disasm_args<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<Option<DisasmArgs>, String> {
+    let mut rom_path = None;
+    let mut set = InstructionSet::XoChip;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-s" | "--set" => {
+                let value = args.next().ok_or(format!("'{}' expects a value", arg))?;
+                set = InstructionSet::from_name(&value)
+                    .ok_or(format!("unknown instruction set '{}'", value))?;
+            }
+            "-o" | "--output" => {
+                output = Some(args.next().ok_or(format!("'{}' expects a value", arg))?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or("no ROM given")?;
+    Ok(Some(DisasmArgs {
+        rom_path,
+        set,
+        output,
+    }))
+}
+
+/// Entry point of `rusteight-disasm`.
+pub fn disasm() {
+    let args = match parse_disasm_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", DISASM_USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, DISASM_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let rom = match std::fs::read(&args.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: {}: {}", args.rom_path, err);
+            std::process::exit(1);
+        }
+    };
+
+    let source = disassemble(&rom, args.set);
+    match &args.output {
+        Some(path) => {
+            if let Err(err) = std::fs::write(path, source) {
+                eprintln!("error: could not write {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", source),
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    audio::{AudioSettings, Waveform},
+    cells::CellStyle,
+    debugger::Breakpoint,
+    keymap::LAYOUTS,
+    quirks::QuirkProfile,
+    trace::TraceSettings,
+};
+
+pub const USAGE: &str = "Usage: rusteight [OPTIONS] <ROM>
+
+Arguments:
+  <ROM>                      Path to the CHIP-8 program to run, or Octo source (.8o)
+
+Options:
+  -i, --ipf <N>              Instructions executed per 60 Hz frame [default: 10]
+  -s, --scale <N>            Size in window pixels of one CHIP-8 pixel [default: 10]
+  -p, --palette <PALETTE>    green, amber, white, gray or BG,FG[,PLANE2,BOTH] as RRGGBB hex
+                             [default: green]
+  -q, --quirks <PROFILE>     vip, chip48, schip or xochip [default: vip]
+  -l, --layout <LAYOUT>      qwerty, azerty, qwertz, dvorak, colemak or arrows
+                             [default: from key map file, else qwerty]
+  -k, --keymap <FILE>        Key map file [default: ~/.config/rusteight/keymap.conf]
+      --tone <HZ>            Buzzer frequency [default: 440]
+      --volume <PERCENT>     Buzzer volume from 0 to 100 [default: 25]
+      --waveform <WAVE>      square, triangle, sawtooth or sine [default: square]
+      --mute                 Start with the buzzer muted, F1 toggles it
+      --lenient              Skip faulting instructions instead of halting
+      --seed <N>             Seed for the random number instruction, so runs repeat
+                             [default: random]
+      --debug                Start paused and read debugger commands from stdin
+      --trace <FILE>         Write a line per instruction to FILE, - for stdout
+      --trace-range <RANGE>  Only trace instructions at addresses START-END, in hex
+      --trace-last <N>       Only write the last N instructions traced, when the CPU faults
+      --record <FILE>        Record keypad input to a movie file
+      --play <FILE>          Play back a movie file's input instead of the keyboard
+      --rewind <SECONDS>     Seconds of play kept for rewinding, 0 to disable [default: 10]
+      --terminal             Draw in the terminal instead of a window, e.g. over SSH
+      --braille              Draw in the terminal with Braille dots instead of half blocks
+      --headless             Run without opening a window and print the final display,
+                             registers and state hash
+      --frames <N>           Frames to run in headless mode [default: 600]
+      --until <COND>         Stop headless mode before running ADDR [if vX OP N]
+      --input <FILE>         Keys to hold in headless mode, lines of FRAME [KEY...]
+      --wav <FILE>           Record the buzzer to a WAV file in headless mode
+  -h, --help                 Print this help
+
+Hotkeys:
+  Esc                        Quit
+  F1                         Mute or unmute the buzzer
+  F5, Space/F6, F7, F8       Pause/resume, step, step over, step out
+  F9, F10                    Save or load the state in the current slot
+  F11                        Select the next of 10 save state slots
+  Backspace (hold)           Rewind
+
+Trace lines, in hex with the state before each instruction runs, then the
+cycle number in decimal and the instruction:
+  PC:0200 OP:6012 V0:00 V1:00 .. VF:00 I:0000 SP:00 DT:00 ST:00 CY:1 LD V0, 0x12";
+
+/// Colours for each combination of the two XO-CHIP planes, the foreground
+/// is plane 1 which is all that CHIP-8 and SUPER-CHIP programs draw to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Palette {
+    pub background: (u8, u8, u8),
+    pub foreground: (u8, u8, u8),
+    pub plane2: (u8, u8, u8),
+    pub overlap: (u8, u8, u8),
+}
+
+impl Palette {
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let (foreground, plane2, overlap) = match name {
+            "green" => ((0, 255, 0), (0, 110, 255), (200, 255, 200)),
+            "amber" => ((255, 176, 0), (180, 60, 0), (255, 240, 200)),
+            "white" => ((255, 255, 255), (110, 110, 110), (190, 190, 190)),
+            "gray" => ((170, 170, 170), (85, 85, 85), (255, 255, 255)),
+            _ => {
+                let colours = name
+                    .split(',')
+                    .map(parse_hex_colour)
+                    .collect::<Option<Vec<_>>>()?;
+                return match colours[..] {
+                    [background, foreground] => Some(Palette {
+                        background,
+                        foreground,
+                        plane2: foreground,
+                        overlap: foreground,
+                    }),
+                    [background, foreground, plane2, overlap] => Some(Palette {
+                        background,
+                        foreground,
+                        plane2,
+                        overlap,
+                    }),
+                    _ => None,
+                };
+            }
+        };
+
+        Some(Palette {
+            background: (0, 0, 0),
+            foreground,
+            plane2,
+            overlap,
+        })
+    }
+
+    /// Colour of a display pixel holding the given plane bits.
+    pub fn colour(&self, pixel: u8) -> (u8, u8, u8) {
+        match pixel & 0b11 {
+            0 => self.background,
+            1 => self.foreground,
+            2 => self.plane2,
+            _ => self.overlap,
+        }
+    }
+}
+
+fn parse_hex_colour(hex: &str) -> Option<(u8, u8, u8)> {
+    let hex = hex.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+
+    Some(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+// Parsed once at startup, so the size of a config is no concern
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Config),
+    Help,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Config {
+    pub rom_path: String,
+    pub instructions_per_frame: u32,
+    pub scale: u32,
+    pub palette: Palette,
+    pub quirks: QuirkProfile,
+    pub layout: Option<String>,
+    pub keymap_path: Option<String>,
+    pub audio: AudioSettings,
+    pub lenient: bool,
+    pub seed: Option<u64>,
+    pub debug: bool,
+    pub trace: TraceSettings,
+    pub rewind_seconds: u32,
+    pub record_path: Option<String>,
+    pub play_path: Option<String>,
+    /// Draw in the terminal rather than a window.
+    pub terminal: Option<CellStyle>,
+    pub headless: bool,
+    pub frames: u64,
+    pub until: Option<Breakpoint>,
+    pub input_path: Option<String>,
+    pub wav_path: Option<String>,
+}
+
+impl Config {
+    pub fn new(rom_path: &str) -> Config {
+        Config {
+            rom_path: String::from(rom_path),
+            instructions_per_frame: 10,
+            scale: 10,
+            palette: Palette::from_name("green").unwrap(),
+            quirks: QuirkProfile::CosmacVip,
+            layout: None,
+            keymap_path: None,
+            audio: AudioSettings::default(),
+            lenient: false,
+            seed: None,
+            debug: false,
+            trace: TraceSettings::default(),
+            rewind_seconds: 10,
+            record_path: None,
+            play_path: None,
+            terminal: None,
+            // Without SDL there is no window to open
+            headless: !cfg!(feature = "sdl"),
+            frames: 600,
+            until: None,
+            input_path: None,
+            wav_path: None,
+        }
+    }
+
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+        let mut config = Config::new("");
+        let mut headless = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-i" | "--ipf" => config.instructions_per_frame = parse_number(&arg, args.next())?,
+                "-s" | "--scale" => config.scale = parse_number(&arg, args.next())?,
+                "-p" | "--palette" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.palette =
+                        Palette::from_name(&value).ok_or(format!("unknown palette '{}'", value))?;
+                }
+                "-q" | "--quirks" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.quirks = QuirkProfile::from_name(&value)
+                        .ok_or(format!("unknown quirk profile '{}'", value))?;
+                }
+                "-l" | "--layout" => {
+                    let value = expect_value(&arg, args.next())?;
+                    if !LAYOUTS.contains(&value.as_str()) {
+                        return Err(format!("unknown layout '{}'", value));
+                    }
+                    config.layout = Some(value);
+                }
+                "-k" | "--keymap" => config.keymap_path = Some(expect_value(&arg, args.next())?),
+                "--tone" => config.audio.frequency = parse_number(&arg, args.next())?,
+                "--volume" => {
+                    let volume: u8 = parse_number(&arg, args.next())?;
+                    config.audio.volume = volume.min(100) as f32 / 100.0;
+                }
+                "--waveform" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.audio.waveform = Waveform::from_name(&value)
+                        .ok_or(format!("unknown waveform '{}'", value))?;
+                }
+                "--mute" => config.audio.muted = true,
+                "--lenient" => config.lenient = true,
+                "--seed" => config.seed = Some(parse_number(&arg, args.next())?),
+                "--debug" => config.debug = true,
+                "--trace" => config.trace.path = Some(expect_value(&arg, args.next())?),
+                "--trace-range" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.trace.range = Some(
+                        parse_range(&value).ok_or(format!("invalid address range '{}'", value))?,
+                    );
+                }
+                "--trace-last" => config.trace.last = Some(parse_number(&arg, args.next())?),
+                "--record" => config.record_path = Some(expect_value(&arg, args.next())?),
+                "--play" => config.play_path = Some(expect_value(&arg, args.next())?),
+                "--rewind" => config.rewind_seconds = parse_number(&arg, args.next())?,
+                "--terminal" => {
+                    config.terminal.get_or_insert(CellStyle::HalfBlocks);
+                }
+                "--braille" => config.terminal = Some(CellStyle::Braille),
+                "--headless" => {
+                    config.headless = true;
+                    headless = true;
+                }
+                "--frames" => config.frames = parse_number(&arg, args.next())?,
+                "--until" => {
+                    let value = expect_value(&arg, args.next())?;
+                    let words: Vec<&str> = value.split_whitespace().collect();
+                    config.until = Some(
+                        Breakpoint::parse(&words)
+                            .map_err(|err| format!("invalid condition '{}': {}", value, err))?,
+                    );
+                }
+                "--input" => config.input_path = Some(expect_value(&arg, args.next())?),
+                "--wav" => config.wav_path = Some(expect_value(&arg, args.next())?),
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+                _ if config.rom_path.is_empty() => config.rom_path = arg,
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+
+        if config.scale == 0 {
+            return Err(String::from("scale must be at least 1"));
+        }
+
+        if config.record_path.is_some() && config.play_path.is_some() {
+            return Err(String::from("--record and --play cannot be used together"));
+        }
+
+        if config.input_path.is_some() && config.play_path.is_some() {
+            return Err(String::from("--input and --play cannot be used together"));
+        }
+
+        if config.terminal.is_some() {
+            if !cfg!(feature = "terminal") {
+                return Err(String::from("this build has no terminal frontend"));
+            }
+            if headless {
+                return Err(String::from(
+                    "--terminal and --headless cannot be used together",
+                ));
+            }
+            if config.debug {
+                return Err(String::from(
+                    "--debug reads stdin, which --terminal needs for keys",
+                ));
+            }
+            // The terminal stands in for the window in builds without SDL
+            config.headless = false;
+        }
+
+        if (config.until.is_some() || config.input_path.is_some()) && !config.headless {
+            return Err(String::from("--until and --input need --headless"));
+        }
+
+        if config.record_path.is_some() && config.headless {
+            return Err(String::from("--record needs a window, not --headless"));
+        }
+
+        if config.rom_path.is_empty() {
+            return Err(String::from("no ROM given"));
+        }
+
+        Ok(Command::Run(config))
+    }
+}
+
+fn expect_value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or(format!("'{}' expects a value", flag))
+}
+
+/// A hex address range such as `200-2FF`.
+fn parse_range(value: &str) -> Option<(u16, u16)> {
+    let (start, end) = value.split_once('-')?;
+    let start = u16::from_str_radix(start.trim_start_matches("0x"), 16).ok()?;
+    let end = u16::from_str_radix(end.trim_start_matches("0x"), 16).ok()?;
+
+    (start <= end).then_some((start, end))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = expect_value(flag, value)?;
+    value
+        .parse()
+        .map_err(|_| format!("'{}' expects a number, got '{}'", flag, value))
+}
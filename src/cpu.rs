@@ -1,12 +1,52 @@
-use rand::Rng;
+use crate::{
+    error::{CpuFault, FaultKind, FaultMode},
+    instruction::Instruction,
+    keypad::KeyStroke,
+    quirks::{QuirkProfile, Quirks},
+    rng::Rng,
+    rom_loader,
+};
+
+pub const RAM_SIZE: usize = 0x10000;
+
+pub const DISPLAY_WIDTH: usize = 128;
+pub const DISPLAY_HEIGHT: usize = 64;
+
+/// Sized for SUPER-CHIP high resolution, low resolution only uses the
+/// top left 64x32 pixels. Each pixel holds one bit per XO-CHIP plane.
+pub type Display = [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+
+/// Something an instruction read or changed, recorded for watchpoints.
+/// Instruction fetches are not included.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+    Read(usize),
+    Write(usize),
+    Reg(usize),
+    Index,
+    DelayTimer,
+    SoundTimer,
+    Stack,
+}
 
-use crate::{keypad::KeyStroke, rom_loader};
+/// State compared before and after an instruction to find what it changed.
+struct Snapshot {
+    regs: [u8; 16],
+    index_reg: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    sp: usize,
+    stack: [u16; 16],
+}
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub struct CPU {
     pub regs: [u8; 16],
-    pub ram: [u8; 4096],
-    pub display: [[u8; 64]; 32],
+    pub ram: [u8; RAM_SIZE],
+    pub display: Display,
+    pub hires: bool,
+    pub planes: u8,
     pub pc: u16,
     pub sp: usize,
     pub index_reg: u16,
@@ -14,27 +54,59 @@ pub struct CPU {
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub keypad: [u8; 16],
-    pub key_pressed: bool,
+    pub key_wait: Option<u8>,
+    pub quirks: Quirks,
+    pub vblank_wait: bool,
+    pub rpl: [u8; 16],
+    pub rpl_dirty: bool,
+    pub exited: bool,
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub fault_mode: FaultMode,
+    /// Memory the program can address, the original 4 KiB unless it targets
+    /// XO-CHIP. Only the first `memory_size` bytes of `ram` are used.
+    pub memory_size: usize,
+    /// Seeded with 0 by `init_cpu`, so runs repeat unless reseeded.
+    pub rng: Rng,
+    /// Record what each instruction accesses in `accesses`.
+    pub watching: bool,
+    pub accesses: Vec<Access>,
 }
 
 impl CPU {
     pub fn init_cpu() -> CPU {
         let regs = [0u8; 16];
-        let ram = [0u8; 4096];
+        let ram = [0u8; RAM_SIZE];
         let pc = 0x200;
         let sp = 0;
         let index_reg = 0;
         let stack = [0u16; 16];
-        let delay_timer = 60;
-        let sound_timer = 60;
-        let display = [[0u8; 64]; 32];
+        let delay_timer = 0;
+        let sound_timer = 0;
+        let display = [[0u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        let hires = false;
+        let planes = 1;
         let keypad = [0u8; 16];
-        let key_pressed = false;
+        let key_wait = None;
+        let quirks = Quirks::preset(QuirkProfile::CosmacVip);
+        let vblank_wait = false;
+        let rpl = [0u8; 16];
+        let rpl_dirty = false;
+        let exited = false;
+        let audio_pattern = [0u8; 16];
+        let pitch = 64;
+        let fault_mode = FaultMode::Strict;
+        let memory_size = rom_loader::memory_size(QuirkProfile::CosmacVip);
+        let rng = Rng::new(0);
+        let watching = false;
+        let accesses = Vec::new();
 
         CPU {
             regs,
             ram,
             display,
+            hires,
+            planes,
             pc,
             sp,
             index_reg,
@@ -42,229 +114,304 @@ impl CPU {
             delay_timer,
             sound_timer,
             keypad,
-            key_pressed,
+            key_wait,
+            quirks,
+            vblank_wait,
+            rpl,
+            rpl_dirty,
+            exited,
+            audio_pattern,
+            pitch,
+            fault_mode,
+            memory_size,
+            rng,
+            watching,
+            accesses,
+        }
+    }
+
+    /// Executes one instruction and returns it. In strict mode a fault
+    /// leaves the CPU at the faulting instruction, in lenient mode it is
+    /// skipped. Once exited the CPU stays on the exit instruction.
+    pub fn tick(&mut self) -> Result<Instruction, CpuFault> {
+        if self.exited {
+            return Ok(Instruction::Exit);
+        }
+
+        let pc = self.pc;
+        let fault = |opcode, kind| CpuFault { pc, opcode, kind };
+        let opcode = self.fetch().map_err(|kind| fault(0, kind))?;
+        let instruction = Instruction::decode(opcode);
+        self.pc = self.pc.wrapping_add(2);
+
+        self.accesses.clear();
+        let before = self.watching.then(|| self.snapshot());
+        let result = self.execute(instruction);
+        if let Some(before) = before {
+            self.record_changes(&before);
+        }
+
+        match result {
+            Err(kind) if self.fault_mode == FaultMode::Strict => {
+                self.pc = pc;
+                Err(fault(opcode, kind))
+            }
+            _ => Ok(instruction),
         }
     }
 
-    pub fn tick(&mut self) -> String {
-        let inst = self.fetch();
-        self.pc += 2;
-        self.execute(inst)
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            regs: self.regs,
+            index_reg: self.index_reg,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            sp: self.sp,
+            stack: self.stack,
+        }
+    }
+
+    fn record_changes(&mut self, before: &Snapshot) {
+        for x in 0..16 {
+            if self.regs[x] != before.regs[x] {
+                self.accesses.push(Access::Reg(x));
+            }
+        }
+        if self.index_reg != before.index_reg {
+            self.accesses.push(Access::Index);
+        }
+        if self.delay_timer != before.delay_timer {
+            self.accesses.push(Access::DelayTimer);
+        }
+        if self.sound_timer != before.sound_timer {
+            self.accesses.push(Access::SoundTimer);
+        }
+        if self.sp != before.sp || self.stack != before.stack {
+            self.accesses.push(Access::Stack);
+        }
+    }
+
+    /// The instruction at PC, without executing it.
+    pub fn peek_instruction(&self) -> Instruction {
+        let hi = self.ram[self.pc as usize];
+        let lo = self.ram[self.pc.wrapping_add(1) as usize];
+
+        Instruction::decode(u16::from_be_bytes([hi, lo]))
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.hires {
+            (128, 64)
+        } else {
+            (64, 32)
+        }
+    }
+
+    pub fn update_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
     }
 
     pub fn set_key(&mut self, key: &KeyStroke) {
-        match key {
-            KeyStroke::Quit => {}
-            KeyStroke::Key(k) => self.keypad = *k,
-            KeyStroke::Next => {}
+        if let KeyStroke::Key(k) = key {
+            self.keypad = *k;
         }
     }
 
-    fn fetch(&mut self) -> u16 {
-        let hi = self.ram[self.pc as usize] as u16;
-        let lo = self.ram[self.pc as usize + 1] as u16;
+    fn fetch(&mut self) -> Result<u16, FaultKind> {
+        self.read_word(self.pc as usize)
+    }
 
-        hi << 8 | lo
+    /// Index into `ram` for `addr`, which wraps around `memory_size` in
+    /// lenient mode.
+    fn ram_index(&self, addr: usize) -> Result<usize, FaultKind> {
+        if addr < self.memory_size {
+            Ok(addr)
+        } else if self.fault_mode == FaultMode::Lenient {
+            Ok(addr % self.memory_size)
+        } else {
+            Err(FaultKind::MemoryOutOfRange(addr))
+        }
     }
 
-    fn execute(&mut self, inst: u16) -> String {
-        let opcode = ((inst & 0xF000) >> 12) as u8;
-        let x = ((inst & 0x0F00) >> 8) as usize;
-        let y = ((inst & 0x00F0) >> 4) as usize;
-        let n = (inst & 0x000F) as u8;
-        let nn = (inst & 0x00FF) as u8;
-        let addr = inst & 0x0FFF;
+    /// Reads data for an instruction, every data access goes through
+    /// `read` and `write` so watchpoints can see it.
+    fn read(&mut self, addr: usize) -> Result<u8, FaultKind> {
+        let index = self.ram_index(addr)?;
+        if self.watching {
+            self.accesses.push(Access::Read(index));
+        }
+        Ok(self.ram[index])
+    }
 
-        match opcode {
-            0x0 => match n {
-                0x0 => {
-                    self.cls();
-                    return format!("Clear Screen");
-                }
-                0xE => {
-                    self.ret();
-                    return format!("Return from subroutine");
-                }
-                _ => {}
-            },
-            0x1 => {
-                self.jmp_to_addr(addr);
-                return format!("Jump to {:x}", addr);
-            }
-            0x2 => {
-                self.call_addr(addr);
-                return format!("Call subroutine at {:x}", addr);
-            }
-            0x3 => {
-                self.se_byte(x, nn);
-                return format!("Skip if reg {} is equal to {}", x, nn);
-            }
-            0x4 => {
-                self.sne_byte(x, nn);
-                return format!("Skip if reg {} is not equal to {}", x, nn);
-            }
-            0x5 => {
-                self.se_reg_reg(x, y);
-                return format!("Skip if reg {} is not equal to reg {}", x, y);
-            }
-            0x6 => {
-                self.set_reg_to_nn(x, nn);
-                return format!("Set reg {} to nn {} ", x, nn);
-            }
-            0x7 => {
-                self.add_val_to_reg(x, nn);
-                return format!("Add val {} to reg {}", nn, x);
-            }
-            0x8 => match n {
-                0x0 => {
-                    self.ld_reg_reg(x, y);
-                    return format!("Set reg {} to reg {}", x, y);
-                }
-                0x1 => {
-                    self.bit_or(x, y);
-                    return format!("Bitwise OR {} to {}", x, y);
-                }
-                0x2 => {
-                    self.bit_and(x, y);
-                    return format!("Bitwise AND {} to {}", x, y,);
-                }
-                0x3 => {
-                    self.bit_xor(x, y);
-                    return format!("Bitwise XOR {} to {}", x, y);
-                }
-                0x4 => {
-                    self.add_reg_reg(x, y);
-                    return format!("Add reg {} to reg {}", y, x);
-                }
-                0x5 => {
-                    self.sub_reg_reg(x, y);
-                    return format!("Sub reg {} from {}", y, x);
-                }
-                0x6 => {
-                    self.shr(x);
-                    return format!("Shift reg {} right by one", x);
-                }
-                0x7 => {
-                    self.sub_not_borrow(x, y);
-                    return format!("Sub not borrow {} from {}", y, x);
-                }
-                0xE => {
-                    self.shl(x);
-                    return format!("Shift reg {} left by one", x);
-                }
-                _ => {}
-            },
-            0x9 => {
-                self.sne_reg_reg(x, y);
-                return format!("Skip if reg {} != {}", x, y);
-            }
-            0xA => {
-                self.set_index_reg_to_addr(addr);
-                return format!("Set index reg to addr {}", addr);
-            }
-            0xB => {
-                self.jmp_to_addr_reg_0(addr);
-                return format!("Jump to addr {} + reg 0", addr);
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), FaultKind> {
+        let index = self.ram_index(addr)?;
+        if self.watching {
+            self.accesses.push(Access::Write(index));
+        }
+        self.ram[index] = value;
+        Ok(())
+    }
+
+    /// Reads from the instruction stream, which is not a data access.
+    fn read_word(&self, addr: usize) -> Result<u16, FaultKind> {
+        let hi = self.ram[self.ram_index(addr)?] as u16;
+        let lo = self.ram[self.ram_index(addr + 1)?] as u16;
+
+        Ok(hi << 8 | lo)
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), FaultKind> {
+        match instruction {
+            Instruction::ScrollDown { n } => self.scroll(0, n as isize),
+            Instruction::ScrollUp { n } => self.scroll(0, -(n as isize)),
+            Instruction::Cls => self.cls(),
+            Instruction::Ret => self.ret()?,
+            Instruction::ScrollRight => self.scroll(4, 0),
+            Instruction::ScrollLeft => self.scroll(-4, 0),
+            Instruction::Exit => self.exit(),
+            Instruction::LowRes => self.set_hires(false),
+            Instruction::HighRes => self.set_hires(true),
+            Instruction::Jmp { addr } => self.jmp_to_addr(addr),
+            Instruction::Call { addr } => self.call_addr(addr)?,
+            Instruction::SeByte { x, nn } => self.se_byte(x, nn),
+            Instruction::SneByte { x, nn } => self.sne_byte(x, nn),
+            Instruction::SeRegReg { x, y } => self.se_reg_reg(x, y),
+            Instruction::LdRangeToRam { x, y } => self.ld_range_to_ram(x, y)?,
+            Instruction::LdRamToRange { x, y } => self.ld_ram_to_range(x, y)?,
+            Instruction::SetRegToNn { x, nn } => self.set_reg_to_nn(x, nn),
+            Instruction::AddValToReg { x, nn } => self.add_val_to_reg(x, nn),
+            Instruction::LdRegReg { x, y } => self.ld_reg_reg(x, y),
+            Instruction::BitOr { x, y } => self.bit_or(x, y),
+            Instruction::BitAnd { x, y } => self.bit_and(x, y),
+            Instruction::BitXor { x, y } => self.bit_xor(x, y),
+            Instruction::AddRegReg { x, y } => self.add_reg_reg(x, y),
+            Instruction::SubRegReg { x, y } => self.sub_reg_reg(x, y),
+            Instruction::Shr { x, y } => self.shr(x, y),
+            Instruction::SubNotBorrow { x, y } => self.sub_not_borrow(x, y),
+            Instruction::Shl { x, y } => self.shl(x, y),
+            Instruction::SneRegReg { x, y } => self.sne_reg_reg(x, y),
+            Instruction::SetIndexRegToAddr { addr } => self.set_index_reg_to_addr(addr),
+            Instruction::JmpToAddrReg0 { addr } => self.jmp_to_addr_reg_0(addr),
+            Instruction::RndNum { x, nn } => self.rnd_num(x, nn),
+            Instruction::Draw { x, y, n } => self.draw(x, y, n)?,
+            Instruction::Skp { x } => self.skp(x),
+            Instruction::Sknp { x } => self.sknp(x),
+            Instruction::LdLongIndex => {
+                let long_addr = self.read_word(self.pc as usize)?;
+                self.ld_long_index(long_addr);
             }
-            0xC => {
-                self.rnd_num(x, nn);
-                return format!("Set reg {} to random number & {}", x, nn);
+            Instruction::SelectPlanes { x } => self.select_planes(x),
+            Instruction::LdAudioPattern => self.ld_audio_pattern()?,
+            Instruction::LdDtToReg { x } => self.ld_dt_to_reg(x),
+            Instruction::LdKey { x } => self.ld_key(x),
+            Instruction::LdRegToDt { x } => self.ld_reg_to_dt(x),
+            Instruction::LdStToReg { x } => self.ld_st_to_reg(x),
+            Instruction::AddIToReg { x } => self.add_i_to_reg(x),
+            Instruction::LdFont { x } => self.ld_font(x),
+            Instruction::LdBigFont { x } => self.ld_big_font(x),
+            Instruction::LdPitch { x } => self.ld_pitch(x),
+            Instruction::Bcd { x } => self.bcd(x)?,
+            Instruction::LdRegToRam { x } => self.ld_reg_to_ram(x)?,
+            Instruction::LdRamToReg { x } => self.ld_ram_to_reg(x)?,
+            Instruction::LdRegToRpl { x } => self.ld_reg_to_rpl(x),
+            Instruction::LdRplToReg { x } => self.ld_rpl_to_reg(x),
+            Instruction::Invalid(_) => return Err(FaultKind::InvalidOpcode),
+        }
+        Ok(())
+    }
+
+    fn cls(&mut self) {
+        for row in self.display.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.planes;
             }
-            0xD => {
-                self.draw(x, y, n);
-                return format!("Draw x {} y {} n {}", x, y, n);
+        }
+    }
+
+    /// Moves the selected planes by dx, dy pixels, filling the uncovered
+    /// area with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.screen_size();
+        let old = self.display;
+
+        for y_coord in 0..height {
+            for x_coord in 0..width {
+                let src_x = x_coord as isize - dx;
+                let src_y = y_coord as isize - dy;
+                let src = if (0..width as isize).contains(&src_x)
+                    && (0..height as isize).contains(&src_y)
+                {
+                    old[src_y as usize][src_x as usize]
+                } else {
+                    0
+                };
+
+                self.display[y_coord][x_coord] =
+                    (old[y_coord][x_coord] & !self.planes) | (src & self.planes);
             }
-            0xE => match n {
-                0xE => {
-                    self.skp(x);
-                    return format!("Skip if key with value at reg {} is pressed", x);
-                }
-                0x1 => {
-                    self.sknp(x);
-                    return format!("Skip if key with value at reg {} is not pressed", x);
-                }
-                _ => {}
-            },
-            0xF => match nn {
-                0x07 => {
-                    self.ld_dt_to_reg(x);
-                    return format!("Load value of delay timer into reg {}", x);
-                }
-                0x0A => {
-                    self.ld_key(x);
-                    return format!("Wait for key press and store value in reg {}", x);
-                }
-                0x15 => {
-                    self.ld_reg_to_dt(x);
-                    return format!("Load reg {} to delay timer", x);
-                }
-                0x18 => {
-                    self.ld_st_to_reg(x);
-                    return format!("Load sound timer to reg {}", x);
-                }
-                0x1E => {
-                    self.add_i_to_reg(x);
-                    return format!("Index reg = index reg + reg {}", x);
-                }
-                0x29 => {
-                    self.ld_font(x);
-                    return format!("load font at location reg {} to index reg", x);
-                }
-                0x33 => {
-                    self.bcd(x);
-                    return format!("Store value of reg {} as bcd in index reg", x);
-                }
-                0x55 => {
-                    self.ld_reg_to_ram(x);
-                    return format!("Store values of reg 0 to reg {} in ram", x);
-                }
-                0x65 => {
-                    self.ld_ram_to_reg(x);
-                    return format!("Load ram into reg 0 to reg {}", x);
-                }
-                _ => {}
-            },
-            _ => {}
         }
-        String::from("")
     }
 
-    fn cls(&mut self) {
-        self.display = [[0u8; 64]; 32];
+    fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = [[0u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
     }
 
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<(), FaultKind> {
+        if self.sp == 0 {
+            return Err(FaultKind::StackUnderflow);
+        }
+
+        self.sp -= 1;
         self.pc = self.stack[self.sp];
-        if self.sp > 0 {
-            self.sp -= 1;
-        };
+        Ok(())
     }
 
     fn jmp_to_addr(&mut self, addr: u16) {
         self.pc = addr;
     }
 
-    fn call_addr(&mut self, addr: u16) {
-        self.sp += 1;
+    fn call_addr(&mut self, addr: u16) -> Result<(), FaultKind> {
+        if self.sp == self.stack.len() {
+            return Err(FaultKind::StackOverflow);
+        }
+
         self.stack[self.sp] = self.pc;
+        self.sp += 1;
         self.pc = addr;
+        Ok(())
     }
 
     fn se_byte(&mut self, x: usize, nn: u8) {
         if self.regs[x] == nn {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
     fn sne_byte(&mut self, x: usize, nn: u8) {
         if self.regs[x] != nn {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
     fn se_reg_reg(&mut self, x: usize, y: usize) {
         if self.regs[x] == self.regs[y] {
-            self.pc += 2;
+            self.skip_next();
+        }
+    }
+
+    /// Skips the next instruction, which is four bytes long if it is the
+    /// XO-CHIP long index load.
+    fn skip_next(&mut self) {
+        if self.read_word(self.pc as usize) == Ok(0xF000) {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -282,61 +429,70 @@ impl CPU {
 
     fn bit_or(&mut self, x: usize, y: usize) {
         self.regs[x] |= self.regs[y];
+        self.reset_vf();
     }
 
     fn bit_and(&mut self, x: usize, y: usize) {
         self.regs[x] &= self.regs[y];
+        self.reset_vf();
     }
 
     fn bit_xor(&mut self, x: usize, y: usize) {
         self.regs[x] ^= self.regs[y];
+        self.reset_vf();
     }
 
-    fn add_reg_reg(&mut self, x: usize, y: usize) {
-        let overflow = self.regs[x].overflowing_add(self.regs[y]);
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
+    }
 
-        if overflow.1 {
-            self.regs[0xF] = 1;
-        };
+    fn add_reg_reg(&mut self, x: usize, y: usize) {
+        let (sum, carry) = self.regs[x].overflowing_add(self.regs[y]);
 
-        self.regs[x] = overflow.0;
+        // VF is written last so that it wins when it is also the target
+        self.regs[x] = sum;
+        self.regs[0xF] = carry as u8;
     }
 
     fn sub_reg_reg(&mut self, x: usize, y: usize) {
-        if self.regs[x] > self.regs[y] {
-            self.regs[0xF] = 1;
-        }
+        let (difference, borrow) = self.regs[x].overflowing_sub(self.regs[y]);
 
-        self.regs[x] = self.regs[x].overflowing_sub(self.regs[y]).0;
+        self.regs[x] = difference;
+        self.regs[0xF] = !borrow as u8;
     }
 
-    fn shr(&mut self, x: usize) {
-        if self.regs[x] & 1 == 1 {
-            self.regs[0xF] = 1;
-        };
+    fn shr(&mut self, x: usize, y: usize) {
+        if self.quirks.shift_uses_vy {
+            self.regs[x] = self.regs[y];
+        }
 
+        let flag = self.regs[x] & 1;
         self.regs[x] >>= 1;
+        self.regs[0xF] = flag;
     }
 
     fn sub_not_borrow(&mut self, x: usize, y: usize) {
-        if self.regs[y] > self.regs[x] {
-            self.regs[0xF] = 1;
-        }
+        let (difference, borrow) = self.regs[y].overflowing_sub(self.regs[x]);
 
-        self.regs[x] = self.regs[x].overflowing_sub(self.regs[y]).0;
+        self.regs[x] = difference;
+        self.regs[0xF] = !borrow as u8;
     }
 
-    fn shl(&mut self, x: usize) {
-        if self.regs[x] >> 7 & 1 == 1 {
-            self.regs[0xF] = 1;
+    fn shl(&mut self, x: usize, y: usize) {
+        if self.quirks.shift_uses_vy {
+            self.regs[x] = self.regs[y];
         }
 
+        let flag = self.regs[x] >> 7 & 1;
         self.regs[x] <<= 1;
+        self.regs[0xF] = flag;
     }
 
     fn sne_reg_reg(&mut self, x: usize, y: usize) {
         if self.regs[x] != self.regs[y] {
-            self.pc += 2;
+            self.skip_next();
         }
     }
 
@@ -345,44 +501,106 @@ impl CPU {
     }
 
     fn jmp_to_addr_reg_0(&mut self, addr: u16) {
-        self.pc = self.regs[0] as u16 + addr;
+        let reg = if self.quirks.jump_uses_vx {
+            (addr >> 8) as usize
+        } else {
+            0
+        };
+
+        self.pc = self.regs[reg] as u16 + addr;
     }
 
     fn rnd_num(&mut self, x: usize, nn: u8) {
-        let mut rng = rand::thread_rng();
-        let rnd_num = rng.gen_range(0..255);
-
-        self.regs[x] = rnd_num & nn;
+        self.regs[x] = self.rng.next_byte() & nn;
     }
 
-    fn draw(&mut self, x: usize, y: usize, n: u8) {
+    fn draw(&mut self, x: usize, y: usize, n: u8) -> Result<(), FaultKind> {
+        let (width, height) = self.screen_size();
+        let x_start = self.regs[x] as usize % width;
+        let y_start = self.regs[y] as usize % height;
+
+        // DXY0 draws a 16x16 sprite stored as two bytes per row
+        let (sprite_width, rows) = if n == 0 { (16, 16) } else { (8, n as usize) };
+
+        // With both planes selected the second plane's sprite follows the first
+        let sprite_len = rows * sprite_width / 8;
+        let sprite_end = self.index_reg as usize + sprite_len * self.planes.count_ones() as usize;
+        if sprite_end > self.index_reg as usize {
+            self.ram_index(sprite_end - 1)?;
+        }
+
         self.regs[0xF] = 0;
 
-        for i in 0..n {
-            let y_coord = (self.regs[y] + i) % 32;
-            let byte = self.ram[(self.index_reg as usize) + i as usize];
-            for bit in 0..8 {
-                let x_coord = (self.regs[x].overflowing_add(bit).0) % 64;
-                let byte_at_disp = self.display[y_coord as usize][x_coord as usize];
-                let pixel_to_turn_on = (byte >> (7 - bit)) & 1;
-                let current_pixel_status = (byte_at_disp >> bit) & 1;
-                if pixel_to_turn_on == 1 && current_pixel_status == 1 {
-                    self.regs[0xF] = 1;
+        let mut addr = self.index_reg as usize;
+        for plane in [1, 2] {
+            if self.planes & plane != 0 {
+                self.draw_plane(plane, (x_start, y_start), (sprite_width, rows), addr)?;
+                addr += sprite_len;
+            }
+        }
+
+        if self.quirks.display_wait {
+            self.vblank_wait = true;
+        }
+        Ok(())
+    }
+
+    fn draw_plane(
+        &mut self,
+        plane: u8,
+        start: (usize, usize),
+        size: (usize, usize),
+        addr: usize,
+    ) -> Result<(), FaultKind> {
+        let (width, height) = self.screen_size();
+        let (x_start, y_start) = start;
+        let (sprite_width, rows) = size;
+        let bytes_per_row = sprite_width / 8;
+
+        for i in 0..rows {
+            let mut y_coord = y_start + i;
+            if y_coord >= height {
+                if self.quirks.clip_sprites {
+                    break;
+                }
+                y_coord %= height;
+            }
+
+            let row_addr = addr + i * bytes_per_row;
+            let mut bits = (self.read(row_addr)? as u16) << 8;
+            if bytes_per_row == 2 {
+                bits |= self.read(row_addr + 1)? as u16;
+            }
+
+            for bit in 0..sprite_width {
+                let mut x_coord = x_start + bit;
+                if x_coord >= width {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    x_coord %= width;
+                }
+
+                if (bits >> (15 - bit)) & 1 == 1 {
+                    if self.display[y_coord][x_coord] & plane != 0 {
+                        self.regs[0xF] = 1;
+                    }
+                    self.display[y_coord][x_coord] ^= plane;
                 }
-                self.display[y_coord as usize][x_coord as usize] ^= pixel_to_turn_on;
             }
         }
+        Ok(())
     }
 
     fn skp(&mut self, x: usize) {
-        if self.keypad[self.regs[x] as usize] == 1 {
-            self.pc += 2;
+        if self.keypad[self.regs[x] as usize & 0xF] == 1 {
+            self.skip_next();
         }
     }
 
     fn sknp(&mut self, x: usize) {
-        if self.keypad[self.regs[x] as usize] != 1 {
-            self.pc += 2;
+        if self.keypad[self.regs[x] as usize & 0xF] != 1 {
+            self.skip_next();
         }
     }
 
@@ -390,16 +608,26 @@ impl CPU {
         self.regs[x] = self.delay_timer;
     }
 
+    /// Like the COSMAC VIP, waits for a key to be pressed and then released
+    /// by repeating the instruction until it is.
     fn ld_key(&mut self, x: usize) {
-        if self.keypad == [0u8; 16] {
-            self.pc -= 2;
-        } else {
-            for i in 0..self.keypad.len() {
-                if self.keypad[i] == 1 {
-                    self.regs[x] = i as u8;
-                }
+        match self.key_wait {
+            Some(key) if self.keypad[key as usize] == 0 => {
+                self.regs[x] = key;
+                self.key_wait = None;
+                return;
+            }
+            Some(_) => {}
+            None => {
+                self.key_wait = self
+                    .keypad
+                    .iter()
+                    .position(|&pressed| pressed == 1)
+                    .map(|key| key as u8);
             }
         }
+
+        self.pc = self.pc.wrapping_sub(2);
     }
 
     fn ld_reg_to_dt(&mut self, x: usize) {
@@ -411,45 +639,122 @@ impl CPU {
     }
 
     fn add_i_to_reg(&mut self, x: usize) {
-        self.index_reg += self.regs[x] as u16;
+        self.index_reg = self.index_reg.wrapping_add(self.regs[x] as u16);
     }
 
     fn ld_font(&mut self, x: usize) {
-        self.index_reg = self.regs[x] as u16 * 5;
+        self.index_reg = (rom_loader::FONT_ADDR + (self.regs[x] as usize & 0xF) * 5) as u16;
     }
 
-    fn bcd(&mut self, x: usize) {
-        self.ram[self.index_reg as usize] = self.regs[x] / 100;
-        self.ram[self.index_reg as usize + 1] = (self.regs[x] % 100) / 10;
-        self.ram[self.index_reg as usize + 2] = self.regs[x] % 10;
+    fn ld_big_font(&mut self, x: usize) {
+        self.index_reg = (rom_loader::BIG_FONT_ADDR + (self.regs[x] as usize & 0xF) * 10) as u16;
+    }
+
+    fn bcd(&mut self, x: usize) -> Result<(), FaultKind> {
+        let start = self.index_reg as usize;
+        let value = self.regs[x];
+
+        // Checked up front so a fault leaves memory untouched
+        self.ram_index(start + 2)?;
+        self.write(start, value / 100)?;
+        self.write(start + 1, (value % 100) / 10)?;
+        self.write(start + 2, value % 10)
     }
 
-    fn ld_reg_to_ram(&mut self, x: usize) {
-        if x == 0 {
-            self.ram[self.index_reg as usize] = self.regs[0];
+    fn ld_reg_to_ram(&mut self, x: usize) -> Result<(), FaultKind> {
+        let start = self.index_reg as usize;
+        self.ram_index(start + x)?;
+        for i in 0..=x {
+            self.write(start + i, self.regs[i])?;
         }
 
-        for i in 0..x + 1 {
-            self.ram[self.index_reg as usize + i] = self.regs[i];
+        if self.quirks.load_store_increments_i {
+            self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);
         }
+        Ok(())
     }
 
-    fn ld_ram_to_reg(&mut self, x: usize) {
-        if x == 0 {
-            self.regs[0] = self.ram[self.index_reg as usize];
+    fn ld_ram_to_reg(&mut self, x: usize) -> Result<(), FaultKind> {
+        let start = self.index_reg as usize;
+        self.ram_index(start + x)?;
+        for i in 0..=x {
+            self.regs[i] = self.read(start + i)?;
         }
 
-        for i in 0..x + 1 {
-            self.regs[i] = self.ram[self.index_reg as usize + i];
+        if self.quirks.load_store_increments_i {
+            self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);
         }
+        Ok(())
+    }
+
+    /// Register transferred at offset i of a 5XY2/5XY3 range, which counts
+    /// down when Y is below X.
+    fn range_reg(x: usize, y: usize, i: usize) -> usize {
+        if x <= y {
+            x + i
+        } else {
+            x - i
+        }
+    }
+
+    fn ld_range_to_ram(&mut self, x: usize, y: usize) -> Result<(), FaultKind> {
+        let start = self.index_reg as usize;
+        self.ram_index(start + x.abs_diff(y))?;
+
+        for i in 0..=x.abs_diff(y) {
+            self.write(start + i, self.regs[CPU::range_reg(x, y, i)])?;
+        }
+        Ok(())
+    }
+
+    fn ld_ram_to_range(&mut self, x: usize, y: usize) -> Result<(), FaultKind> {
+        let start = self.index_reg as usize;
+        self.ram_index(start + x.abs_diff(y))?;
+
+        for i in 0..=x.abs_diff(y) {
+            self.regs[CPU::range_reg(x, y, i)] = self.read(start + i)?;
+        }
+        Ok(())
+    }
+
+    fn ld_long_index(&mut self, addr: u16) {
+        self.index_reg = addr;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn select_planes(&mut self, x: usize) {
+        self.planes = x as u8 & 0b11;
+    }
+
+    fn ld_audio_pattern(&mut self) -> Result<(), FaultKind> {
+        let start = self.index_reg as usize;
+        self.ram_index(start + 15)?;
+
+        for i in 0..16 {
+            self.audio_pattern[i] = self.read(start + i)?;
+        }
+        Ok(())
+    }
+
+    fn ld_pitch(&mut self, x: usize) {
+        self.pitch = self.regs[x];
+    }
+
+    fn ld_reg_to_rpl(&mut self, x: usize) {
+        self.rpl[..=x].copy_from_slice(&self.regs[..=x]);
+        self.rpl_dirty = true;
+    }
+
+    fn ld_rpl_to_reg(&mut self, x: usize) {
+        self.regs[..=x].copy_from_slice(&self.rpl[..=x]);
     }
 }
 
-#[allow(dead_code)]
+#[cfg(test)]
 pub fn init_test_cpu() -> CPU {
     let path = String::from("./src/ROMS/IBM.ch8");
     let mut cpu = CPU::init_cpu();
-    cpu.ram = rom_loader::load_rom(path);
+    cpu.ram = rom_loader::load_rom(path, QuirkProfile::CosmacVip).unwrap();
 
     cpu
 }
@@ -0,0 +1,549 @@
+use std::{
+    fmt,
+    sync::mpsc::{self, Receiver},
+};
+
+use crate::{
+    cpu::{Access, CPU},
+    instruction::Instruction,
+};
+
+pub const HELP: &str = "Commands:
+  c, continue                    Resume running
+  p, pause                       Pause
+  s, step                        Run one instruction
+  n, next                        Run one instruction, running calls to completion
+  finish                         Run until the current subroutine returns
+  until ADDR                     Run until PC reaches ADDR
+  b, break ADDR [if vX OP N]     Stop at ADDR, OP is ==, !=, <, >, <= or >=
+  d, delete ADDR                 Remove the breakpoints at ADDR
+  w, watch WATCH                 Stop after WATCH is accessed, which is
+                                 ADDR[-END] [r|w|rw] for memory, writes by
+                                 default, or vX, i, delay, buzzer or stack
+                                 for changes to them
+  unwatch WATCH                  Remove a watchpoint
+  breakpoints                    List breakpoints and watchpoints
+  r, regs                        Print the registers
+  q, quit                        Quit
+  h, help                        Print this help
+Addresses are hex. Hotkeys: F5 pause/resume, Space or F6 step, F7 step
+over, F8 step out.";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+impl Compare {
+    fn from_name(name: &str) -> Option<Compare> {
+        match name {
+            "==" => Some(Compare::Eq),
+            "!=" => Some(Compare::Ne),
+            "<" => Some(Compare::Lt),
+            ">" => Some(Compare::Gt),
+            "<=" => Some(Compare::Le),
+            ">=" => Some(Compare::Ge),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Compare::Eq => "==",
+            Compare::Ne => "!=",
+            Compare::Lt => "<",
+            Compare::Gt => ">",
+            Compare::Le => "<=",
+            Compare::Ge => ">=",
+        }
+    }
+}
+
+/// Compares a register with a value, `v3 == 5`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RegCondition {
+    pub x: usize,
+    pub compare: Compare,
+    pub value: u8,
+}
+
+impl RegCondition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        let reg = cpu.regs[self.x];
+
+        match self.compare {
+            Compare::Eq => reg == self.value,
+            Compare::Ne => reg != self.value,
+            Compare::Lt => reg < self.value,
+            Compare::Gt => reg > self.value,
+            Compare::Le => reg <= self.value,
+            Compare::Ge => reg >= self.value,
+        }
+    }
+}
+
+/// Stops execution before the instruction at `addr`, if the condition
+/// holds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<RegCondition>,
+}
+
+impl Breakpoint {
+    /// `ADDR` or `ADDR if vX OP N`, already split into words.
+    pub fn parse(words: &[&str]) -> Result<Breakpoint, String> {
+        let condition = match words {
+            [_] => None,
+            [_, "if", reg, compare, value] => {
+                let x =
+                    parse_register(reg).ok_or(format!("expected a register, found '{}'", reg))?;
+                let compare = Compare::from_name(compare)
+                    .ok_or(format!("unknown comparison '{}'", compare))?;
+                let value = match value.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => value.parse(),
+                }
+                .map_err(|_| format!("expected a byte, found '{}'", value))?;
+
+                Some(RegCondition { x, compare, value })
+            }
+            _ => {
+                return Err(format!(
+                    "expected ADDR [if vX OP N], found '{}'",
+                    words.join(" ")
+                ))
+            }
+        };
+
+        Ok(Breakpoint {
+            addr: parse_addr(words[0])?,
+            condition,
+        })
+    }
+
+    /// The CPU is about to run the instruction at `addr` and the condition
+    /// holds.
+    pub fn hits(&self, cpu: &CPU) -> bool {
+        self.addr == cpu.pc && self.condition.is_none_or(|condition| condition.holds(cpu))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#06x}", self.addr)?;
+        if let Some(condition) = self.condition {
+            write!(
+                f,
+                " if v{:x} {} {}",
+                condition.x,
+                condition.compare.name(),
+                condition.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Which memory accesses a watchpoint stops on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Stops execution after an instruction accesses memory in a range or
+/// changes a register.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Watch {
+    Ram {
+        start: usize,
+        end: usize,
+        kind: WatchKind,
+    },
+    Reg(usize),
+    Index,
+    DelayTimer,
+    SoundTimer,
+    Stack,
+}
+
+impl Watch {
+    fn parse(words: &[&str]) -> Result<Watch, String> {
+        let watch = match words {
+            ["i"] => Watch::Index,
+            ["delay"] => Watch::DelayTimer,
+            ["buzzer"] => Watch::SoundTimer,
+            ["stack"] => Watch::Stack,
+            [reg] if parse_register(reg).is_some() => Watch::Reg(parse_register(reg).unwrap()),
+            [range] | [range, _] => {
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+                    None => (parse_addr(range)?, parse_addr(range)?),
+                };
+                let kind = match words.get(1) {
+                    None | Some(&"w") => WatchKind::Write,
+                    Some(&"r") => WatchKind::Read,
+                    Some(&"rw") => WatchKind::ReadWrite,
+                    Some(kind) => return Err(format!("expected r, w or rw, found '{}'", kind)),
+                };
+                if start > end {
+                    return Err(format!("{} is an empty range", range));
+                }
+
+                Watch::Ram {
+                    start: start as usize,
+                    end: end as usize,
+                    kind,
+                }
+            }
+            _ => {
+                return Err(String::from(
+                    "expected an address range or register to watch",
+                ))
+            }
+        };
+
+        Ok(watch)
+    }
+
+    fn matches(&self, access: &Access) -> bool {
+        match (*self, *access) {
+            (Watch::Ram { start, end, kind }, Access::Read(addr)) => {
+                kind != WatchKind::Write && (start..=end).contains(&addr)
+            }
+            (Watch::Ram { start, end, kind }, Access::Write(addr)) => {
+                kind != WatchKind::Read && (start..=end).contains(&addr)
+            }
+            (Watch::Reg(x), Access::Reg(reg)) => x == reg,
+            (Watch::Index, Access::Index)
+            | (Watch::DelayTimer, Access::DelayTimer)
+            | (Watch::SoundTimer, Access::SoundTimer)
+            | (Watch::Stack, Access::Stack) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Ram { start, end, kind } => {
+                write!(f, "watch {:#06x}", start)?;
+                if end != start {
+                    write!(f, "-{:#06x}", end)?;
+                }
+                match kind {
+                    WatchKind::Read => write!(f, " r"),
+                    WatchKind::Write => write!(f, " w"),
+                    WatchKind::ReadWrite => write!(f, " rw"),
+                }
+            }
+            Watch::Reg(x) => write!(f, "watch v{:x}", x),
+            Watch::Index => write!(f, "watch i"),
+            Watch::DelayTimer => write!(f, "watch delay"),
+            Watch::SoundTimer => write!(f, "watch buzzer"),
+            Watch::Stack => write!(f, "watch stack"),
+        }
+    }
+}
+
+fn describe(access: &Access) -> String {
+    match access {
+        Access::Read(addr) => format!("read {:#06x}", addr),
+        Access::Write(addr) => format!("write {:#06x}", addr),
+        Access::Reg(x) => format!("write v{:x}", x),
+        Access::Index => String::from("write i"),
+        Access::DelayTimer => String::from("write delay"),
+        Access::SoundTimer => String::from("write buzzer"),
+        Access::Stack => String::from("write stack"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DebugCommand {
+    Continue,
+    Pause,
+    Step,
+    StepOver,
+    StepOut,
+    RunTo(u16),
+    Break(Breakpoint),
+    Delete(u16),
+    Watch(Watch),
+    Unwatch(Watch),
+    List,
+    Registers,
+    Quit,
+    Help,
+}
+
+impl DebugCommand {
+    pub fn parse(line: &str) -> Result<DebugCommand, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let command = match words[..] {
+            ["c" | "continue"] => DebugCommand::Continue,
+            ["p" | "pause"] => DebugCommand::Pause,
+            ["s" | "step"] => DebugCommand::Step,
+            ["n" | "next"] => DebugCommand::StepOver,
+            ["finish"] => DebugCommand::StepOut,
+            ["until", addr] => DebugCommand::RunTo(parse_addr(addr)?),
+            ["b" | "break", ref breakpoint @ ..] => {
+                DebugCommand::Break(Breakpoint::parse(breakpoint)?)
+            }
+            ["d" | "delete", addr] => DebugCommand::Delete(parse_addr(addr)?),
+            ["w" | "watch", ref watch @ ..] => DebugCommand::Watch(Watch::parse(watch)?),
+            ["unwatch", ref watch @ ..] => DebugCommand::Unwatch(Watch::parse(watch)?),
+            ["breakpoints"] => DebugCommand::List,
+            ["r" | "regs"] => DebugCommand::Registers,
+            ["q" | "quit"] => DebugCommand::Quit,
+            ["h" | "help"] => DebugCommand::Help,
+            _ => return Err(format!("unknown command '{}', try 'help'", line.trim())),
+        };
+
+        Ok(command)
+    }
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    let digit = text.strip_prefix('v')?;
+    if digit.len() != 1 {
+        return None;
+    }
+
+    usize::from_str_radix(digit, 16).ok()
+}
+
+fn parse_addr(text: &str) -> Result<u16, String> {
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("expected an address, found '{}'", text))
+}
+
+/// Where a resumed run pauses by itself.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Target {
+    /// After the next instruction.
+    Step,
+    Addr(u16),
+    /// Back from a call stepped over, at the same stack depth.
+    Return {
+        pc: u16,
+        sp: usize,
+    },
+    /// Out of the subroutine running at this stack depth.
+    Out {
+        sp: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    pub paused: bool,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    target: Option<Target>,
+    /// Resuming from a breakpoint must not stop on it again straight away.
+    resume_from: Option<u16>,
+    /// Address and instruction last run while watching, which made the
+    /// accesses the CPU has recorded.
+    last_run: Option<(u16, Instruction)>,
+    /// The breakpoint or watchpoint that paused execution, reported by
+    /// `break_reason`.
+    reason: Option<String>,
+}
+
+impl Debugger {
+    /// Checked before every instruction while running, pauses at
+    /// breakpoints, watchpoints and the target of `step`, `next`, `finish`
+    /// and `until`.
+    pub fn should_break(&mut self, cpu: &CPU) -> bool {
+        if self.resume_from.take() == Some(cpu.pc) {
+            self.note_instruction(cpu);
+            return false;
+        }
+
+        let reached = match self.target {
+            Some(Target::Step) => true,
+            Some(Target::Addr(addr)) => cpu.pc == addr,
+            Some(Target::Return { pc, sp }) => cpu.pc == pc && cpu.sp == sp,
+            Some(Target::Out { sp }) => cpu.sp < sp,
+            None => false,
+        };
+        let watch_hit = self.check_watches(cpu);
+        let hit = self
+            .breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.hits(cpu));
+
+        self.reason =
+            watch_hit.or_else(|| hit.map(|breakpoint| format!("breakpoint {}", breakpoint)));
+        if reached || self.reason.is_some() {
+            self.paused = true;
+            self.target = None;
+            return true;
+        }
+
+        self.note_instruction(cpu);
+        false
+    }
+
+    /// Why `should_break` paused, along with where.
+    pub fn break_reason(&mut self, cpu: &CPU) -> String {
+        match self.reason.take() {
+            Some(reason) => format!("{}, paused at {}", reason, location(cpu)),
+            None => format!("paused at {}", location(cpu)),
+        }
+    }
+
+    fn note_instruction(&mut self, cpu: &CPU) {
+        if cpu.watching {
+            self.last_run = Some((cpu.pc, cpu.peek_instruction()));
+        }
+    }
+
+    /// The first watchpoint hit by the last instruction run, described with
+    /// the instruction.
+    fn check_watches(&mut self, cpu: &CPU) -> Option<String> {
+        let (pc, instruction) = self.last_run.take()?;
+        let access = cpu
+            .accesses
+            .iter()
+            .find(|access| self.watches.iter().any(|watch| watch.matches(access)))?;
+
+        Some(format!(
+            "watchpoint: {} by {:#06x}  {}",
+            describe(access),
+            pc,
+            instruction
+        ))
+    }
+
+    /// Carries out a command, returning what to print. Commands that run
+    /// the CPU resume it until `should_break` pauses it again, quitting is
+    /// left to the caller.
+    pub fn apply(&mut self, command: DebugCommand, cpu: &mut CPU) -> String {
+        match command {
+            DebugCommand::Continue => {
+                self.resume(cpu, None);
+                String::new()
+            }
+            DebugCommand::Pause => {
+                self.paused = true;
+                self.target = None;
+                format!("paused at {}", location(cpu))
+            }
+            DebugCommand::Step => {
+                self.resume(cpu, Some(Target::Step));
+                String::new()
+            }
+            DebugCommand::StepOver => match cpu.peek_instruction() {
+                Instruction::Call { .. } => {
+                    let target = Target::Return {
+                        pc: cpu.pc.wrapping_add(2),
+                        sp: cpu.sp,
+                    };
+                    self.resume(cpu, Some(target));
+                    String::new()
+                }
+                _ => self.apply(DebugCommand::Step, cpu),
+            },
+            DebugCommand::StepOut if cpu.sp == 0 => String::from("not in a subroutine"),
+            DebugCommand::StepOut => {
+                self.resume(cpu, Some(Target::Out { sp: cpu.sp }));
+                String::new()
+            }
+            DebugCommand::RunTo(addr) => {
+                self.resume(cpu, Some(Target::Addr(addr)));
+                String::new()
+            }
+            DebugCommand::Break(breakpoint) => {
+                self.breakpoints.push(breakpoint);
+                format!("breakpoint at {}", breakpoint)
+            }
+            DebugCommand::Delete(addr) => {
+                let count = self.breakpoints.len();
+                self.breakpoints
+                    .retain(|breakpoint| breakpoint.addr != addr);
+                format!("deleted {} breakpoint(s)", count - self.breakpoints.len())
+            }
+            DebugCommand::Watch(watch) => {
+                self.watches.push(watch);
+                cpu.watching = true;
+                watch.to_string()
+            }
+            DebugCommand::Unwatch(watch) => {
+                let count = self.watches.len();
+                self.watches.retain(|&other| other != watch);
+                cpu.watching = !self.watches.is_empty();
+                format!("deleted {} watchpoint(s)", count - self.watches.len())
+            }
+            DebugCommand::List if self.breakpoints.is_empty() && self.watches.is_empty() => {
+                String::from("no breakpoints")
+            }
+            DebugCommand::List => self
+                .breakpoints
+                .iter()
+                .map(|breakpoint| breakpoint.to_string())
+                .chain(self.watches.iter().map(|watch| watch.to_string()))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugCommand::Registers => registers(cpu),
+            DebugCommand::Help => String::from(HELP),
+            DebugCommand::Quit => String::new(),
+        }
+    }
+
+    fn resume(&mut self, cpu: &CPU, target: Option<Target>) {
+        self.paused = false;
+        self.target = target;
+        self.resume_from = Some(cpu.pc);
+    }
+}
+
+/// PC and the instruction there, `0x0204  LD V0, 0x01`.
+pub fn location(cpu: &CPU) -> String {
+    format!("{:#06x}  {}", cpu.pc, cpu.peek_instruction())
+}
+
+pub fn registers(cpu: &CPU) -> String {
+    let regs: Vec<String> = cpu
+        .regs
+        .iter()
+        .enumerate()
+        .map(|(x, reg)| format!("V{:X} {:02X}", x, reg))
+        .collect();
+
+    format!(
+        "{}\n{}\nI {:04X}  SP {}  DT {}  ST {}  PC {}",
+        regs[..8].join("  "),
+        regs[8..].join("  "),
+        cpu.index_reg,
+        cpu.sp,
+        cpu.delay_timer,
+        cpu.sound_timer,
+        location(cpu)
+    )
+}
+
+/// Reads debugger commands from stdin on another thread, so the window
+/// keeps responding while waiting for input.
+pub fn spawn_prompt() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
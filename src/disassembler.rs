@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::instruction::Instruction;
+
+/// Programs are loaded at this address.
+pub const PROGRAM_START: usize = 0x200;
+
+/// Which opcodes count as code, anything else is treated as data.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InstructionSet {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl InstructionSet {
+    pub fn from_name(name: &str) -> Option<InstructionSet> {
+        match name {
+            "chip8" => Some(InstructionSet::Chip8),
+            "schip" => Some(InstructionSet::Schip),
+            "xochip" => Some(InstructionSet::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        let schip = matches!(
+            instruction,
+            Instruction::ScrollDown { .. }
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Exit
+                | Instruction::LowRes
+                | Instruction::HighRes
+                | Instruction::LdBigFont { .. }
+                | Instruction::LdRegToRpl { .. }
+                | Instruction::LdRplToReg { .. }
+        );
+        let xochip = matches!(
+            instruction,
+            Instruction::ScrollUp { .. }
+                | Instruction::LdRangeToRam { .. }
+                | Instruction::LdRamToRange { .. }
+                | Instruction::LdLongIndex
+                | Instruction::SelectPlanes { .. }
+                | Instruction::LdAudioPattern
+                | Instruction::LdPitch { .. }
+        );
+
+        match self {
+            _ if matches!(instruction, Instruction::Invalid(_)) => false,
+            InstructionSet::Chip8 => !schip && !xochip,
+            InstructionSet::Schip => !xochip,
+            InstructionSet::XoChip => true,
+        }
+    }
+}
+
+/// Byte ranges of a ROM found to be code, and the addresses it refers to.
+struct Analysis {
+    /// Start address of every instruction reached from 0x200.
+    code: BTreeMap<usize, Instruction>,
+    /// Bytes covered by an instruction.
+    code_bytes: BTreeSet<usize>,
+    /// Addresses jumped to, called or loaded into I.
+    code_labels: BTreeSet<usize>,
+    data_labels: BTreeSet<usize>,
+}
+
+fn read_word(rom: &[u8], addr: usize) -> Option<u16> {
+    let offset = addr.checked_sub(PROGRAM_START)?;
+    let hi = *rom.get(offset)? as u16;
+    let lo = *rom.get(offset + 1)? as u16;
+
+    Some(hi << 8 | lo)
+}
+
+/// Follows every path through the program from 0x200, taking both sides of
+/// skips, jumps and calls. Jump tables behind `jump0` are assumed to start
+/// at the base address.
+fn analyse(rom: &[u8], set: InstructionSet) -> Analysis {
+    let mut analysis = Analysis {
+        code: BTreeMap::new(),
+        code_bytes: BTreeSet::new(),
+        code_labels: BTreeSet::from([PROGRAM_START]),
+        data_labels: BTreeSet::new(),
+    };
+    let mut pending = vec![PROGRAM_START];
+    let in_rom = |addr: usize| (PROGRAM_START..PROGRAM_START + rom.len()).contains(&addr);
+
+    while let Some(mut addr) = pending.pop() {
+        while !analysis.code.contains_key(&addr) {
+            let instruction = match read_word(rom, addr).map(Instruction::decode) {
+                Some(instruction) if set.supports(&instruction) => instruction,
+                _ => break,
+            };
+            if instruction == Instruction::LdLongIndex {
+                match read_word(rom, addr + 2) {
+                    Some(long_addr) if in_rom(long_addr as usize) => {
+                        analysis.data_labels.insert(long_addr as usize);
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+
+            let size = instruction.size();
+            analysis.code.insert(addr, instruction);
+            analysis.code_bytes.extend(addr..addr + size);
+            let next = addr + size;
+
+            match instruction {
+                Instruction::Jmp { addr: target } | Instruction::JmpToAddrReg0 { addr: target } => {
+                    if in_rom(target as usize) {
+                        analysis.code_labels.insert(target as usize);
+                        pending.push(target as usize);
+                    }
+                    break;
+                }
+                Instruction::Call { addr: target } if in_rom(target as usize) => {
+                    analysis.code_labels.insert(target as usize);
+                    pending.push(target as usize);
+                }
+                Instruction::Ret | Instruction::Exit => break,
+                Instruction::SeByte { .. }
+                | Instruction::SneByte { .. }
+                | Instruction::SeRegReg { .. }
+                | Instruction::SneRegReg { .. }
+                | Instruction::Skp { .. }
+                | Instruction::Sknp { .. } => {
+                    // Skipping the long index load skips four bytes
+                    let skipped = match read_word(rom, next) {
+                        Some(0xF000) if set == InstructionSet::XoChip => 4,
+                        _ => 2,
+                    };
+                    pending.push(next + skipped);
+                }
+                Instruction::SetIndexRegToAddr { addr: target } if in_rom(target as usize) => {
+                    analysis.data_labels.insert(target as usize);
+                }
+                _ => {}
+            }
+
+            addr = next;
+        }
+    }
+
+    analysis
+}
+
+/// Disassembles a ROM loaded at 0x200 into Octo source. Every line is
+/// commented with its address and bytes, bytes that are never reached as
+/// code are written out as data.
+pub fn disassemble(rom: &[u8], set: InstructionSet) -> String {
+    let analysis = analyse(rom, set);
+    let label = |addr: usize| {
+        if addr == PROGRAM_START {
+            String::from("main")
+        } else if analysis.code_labels.contains(&addr) {
+            format!("code_{:03x}", addr)
+        } else if analysis.data_labels.contains(&addr) {
+            format!("data_{:03x}", addr)
+        } else {
+            format!("{:#05x}", addr)
+        }
+    };
+    let has_label =
+        |addr: usize| analysis.code_labels.contains(&addr) || analysis.data_labels.contains(&addr);
+
+    let mut output = String::new();
+    let end = PROGRAM_START + rom.len();
+    let mut addr = PROGRAM_START;
+
+    while addr < end {
+        if has_label(addr) {
+            output.push_str(&format!(": {}\n", label(addr)));
+        }
+
+        // Code jumping into the middle of another instruction is shown as data
+        let instruction = analysis
+            .code
+            .get(&addr)
+            .filter(|instruction| !(addr + 1..addr + instruction.size()).any(has_label));
+
+        let (text, size) = match instruction {
+            Some(instruction) => (
+                octo_syntax(instruction, rom, addr, &label),
+                instruction.size(),
+            ),
+            None => {
+                // Data runs until the next code, label or eight bytes
+                let mut size = 1;
+                while size < 8
+                    && addr + size < end
+                    && !analysis.code_bytes.contains(&(addr + size))
+                    && !has_label(addr + size)
+                {
+                    size += 1;
+                }
+                let bytes: Vec<String> = rom[addr - PROGRAM_START..addr - PROGRAM_START + size]
+                    .iter()
+                    .map(|byte| format!("{:#04x}", byte))
+                    .collect();
+                (bytes.join(" "), size)
+            }
+        };
+
+        let hex: String = rom[addr - PROGRAM_START..addr - PROGRAM_START + size]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        output.push_str(&format!("\t{:<39} # {:04X}  {}\n", text, addr, hex));
+        addr += size;
+    }
+
+    output
+}
+
+fn octo_syntax(
+    instruction: &Instruction,
+    rom: &[u8],
+    addr: usize,
+    label: &dyn Fn(usize) -> String,
+) -> String {
+    match *instruction {
+        Instruction::ScrollDown { n } => format!("scroll-down {}", n),
+        Instruction::ScrollUp { n } => format!("scroll-up {}", n),
+        Instruction::Cls => String::from("clear"),
+        Instruction::Ret => String::from("return"),
+        Instruction::ScrollRight => String::from("scroll-right"),
+        Instruction::ScrollLeft => String::from("scroll-left"),
+        Instruction::Exit => String::from("exit"),
+        Instruction::LowRes => String::from("lores"),
+        Instruction::HighRes => String::from("hires"),
+        Instruction::Jmp { addr } => format!("jump {}", label(addr as usize)),
+        Instruction::Call { addr } => format!(":call {}", label(addr as usize)),
+        // Octo's conditions say when the next instruction runs, the
+        // opposite of when it is skipped
+        Instruction::SeByte { x, nn } => format!("if v{:x} != {:#04x} then", x, nn),
+        Instruction::SneByte { x, nn } => format!("if v{:x} == {:#04x} then", x, nn),
+        Instruction::SeRegReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SneRegReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::Skp { x } => format!("if v{:x} -key then", x),
+        Instruction::Sknp { x } => format!("if v{:x} key then", x),
+        Instruction::LdRangeToRam { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LdRamToRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Instruction::SetRegToNn { x, nn } => format!("v{:x} := {:#04x}", x, nn),
+        Instruction::AddValToReg { x, nn } => format!("v{:x} += {:#04x}", x, nn),
+        Instruction::LdRegReg { x, y } => format!("v{:x} := v{:x}", x, y),
+        Instruction::BitOr { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Instruction::BitAnd { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Instruction::BitXor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddRegReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Instruction::SubRegReg { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Instruction::Shr { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubNotBorrow { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Instruction::Shl { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SetIndexRegToAddr { addr } => format!("i := {}", label(addr as usize)),
+        Instruction::JmpToAddrReg0 { addr } => format!("jump0 {}", label(addr as usize)),
+        Instruction::RndNum { x, nn } => format!("v{:x} := random {:#04x}", x, nn),
+        Instruction::Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::LdLongIndex => {
+            let long_addr = read_word(rom, addr + 2).unwrap_or(0);
+            format!("i := long {}", label(long_addr as usize))
+        }
+        Instruction::SelectPlanes { x } => format!("plane {}", x),
+        Instruction::LdAudioPattern => String::from("audio"),
+        Instruction::LdDtToReg { x } => format!("v{:x} := delay", x),
+        Instruction::LdKey { x } => format!("v{:x} := key", x),
+        Instruction::LdRegToDt { x } => format!("delay := v{:x}", x),
+        Instruction::LdStToReg { x } => format!("buzzer := v{:x}", x),
+        Instruction::AddIToReg { x } => format!("i += v{:x}", x),
+        Instruction::LdFont { x } => format!("i := hex v{:x}", x),
+        Instruction::LdBigFont { x } => format!("i := bighex v{:x}", x),
+        Instruction::LdPitch { x } => format!("pitch := v{:x}", x),
+        Instruction::Bcd { x } => format!("bcd v{:x}", x),
+        Instruction::LdRegToRam { x } => format!("save v{:x}", x),
+        Instruction::LdRamToReg { x } => format!("load v{:x}", x),
+        Instruction::LdRegToRpl { x } => format!("saveflags v{:x}", x),
+        Instruction::LdRplToReg { x } => format!("loadflags v{:x}", x),
+        Instruction::Invalid(inst) => format!("{:#04x} {:#04x}", inst >> 8, inst & 0xFF),
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use crate::assembler::AssembleError;
+
+/// Everything that can stop the emulator from starting.
+#[derive(Debug, PartialEq)]
+pub enum StartupError {
+    FileNotFound(String),
+    Unreadable {
+        path: String,
+        reason: String,
+    },
+    RomTooLarge {
+        path: String,
+        size: usize,
+        max: usize,
+    },
+    EmptyRom(String),
+    Compile(AssembleError),
+    TraceUnwritable {
+        path: String,
+        reason: String,
+    },
+    MovieUnreadable {
+        path: String,
+        reason: String,
+    },
+    MovieWrongRom(String),
+    InputUnreadable {
+        path: String,
+        reason: String,
+    },
+    FontUnreadable(String),
+    SdlInit(String),
+    TerminalInit(String),
+    /// Keys the key map binds that a terminal never reports.
+    TerminalKeys(Vec<String>),
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartupError::FileNotFound(path) => write!(f, "{}: no such file", path),
+            StartupError::Unreadable { path, reason } => {
+                write!(f, "{}: could not be read: {}", path, reason)
+            }
+            StartupError::RomTooLarge { path, size, max } => write!(
+                f,
+                "{}: ROM is {} bytes, the most that fits in memory is {}",
+                path, size, max
+            ),
+            StartupError::EmptyRom(path) => write!(f, "{}: ROM is empty", path),
+            StartupError::Compile(err) => write!(f, "{}", err),
+            StartupError::TraceUnwritable { path, reason } => {
+                write!(f, "{}: could not create trace file: {}", path, reason)
+            }
+            StartupError::MovieUnreadable { path, reason } => {
+                write!(f, "{}: could not read movie: {}", path, reason)
+            }
+            StartupError::MovieWrongRom(path) => {
+                write!(f, "{}: movie was recorded with a different ROM", path)
+            }
+            StartupError::InputUnreadable { path, reason } => {
+                write!(f, "{}: could not read input script: {}", path, reason)
+            }
+            StartupError::FontUnreadable(reason) => write!(f, "could not load font: {}", reason),
+            StartupError::SdlInit(reason) => write!(f, "could not start SDL: {}", reason),
+            StartupError::TerminalInit(reason) => {
+                write!(f, "could not set up the terminal: {}", reason)
+            }
+            StartupError::TerminalKeys(keys) => write!(
+                f,
+                "the terminal cannot read {}, bind other keys to use --terminal",
+                keys.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// What went wrong while executing an instruction.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FaultKind {
+    InvalidOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfRange(usize),
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultKind::InvalidOpcode => write!(f, "invalid opcode"),
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "stack underflow"),
+            FaultKind::MemoryOutOfRange(addr) => {
+                write!(f, "memory address {:#x} out of range", addr)
+            }
+        }
+    }
+}
+
+/// An instruction the CPU could not execute, `pc` is left pointing at it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CpuFault {
+    pub pc: u16,
+    pub opcode: u16,
+    pub kind: FaultKind,
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#06x} (opcode {:04X})",
+            self.kind, self.pc, self.opcode
+        )
+    }
+}
+
+impl std::error::Error for CpuFault {}
+
+/// Whether a fault halts the CPU or the offending instruction is skipped,
+/// with memory addresses wrapping around.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FaultMode {
+    Strict,
+    Lenient,
+}
@@ -0,0 +1,260 @@
+use std::path::Path;
+
+use crate::{
+    audio::AudioSink,
+    config::Config,
+    debugger::{self, DebugCommand, Debugger},
+    keypad::{InputSource, KeyStroke},
+    machine::Machine,
+    movie::{advance_playback, Movie, Playback},
+    rewind::Rewind,
+    savestate,
+    scheduler::{Clock, Pacer},
+    storage,
+    trace::Tracer,
+    video::VideoSink,
+};
+
+/// The devices an interactive run talks to. Any combination works, such as
+/// an SDL window with scripted input.
+pub struct Frontend<'a> {
+    pub video: &'a mut dyn VideoSink,
+    pub input: &'a mut dyn InputSource,
+    pub audio: &'a mut dyn AudioSink,
+}
+
+/// Runs the ROM until it exits or the input quits, with the debugger, save
+/// states, rewind and movies driven by hotkeys.
+pub fn run<C: Clock>(
+    machine: &mut Machine,
+    clock: C,
+    config: &Config,
+    flags_path: &Path,
+    mut tracer: Option<Tracer>,
+    mut playback: Option<Playback>,
+    frontend: Frontend,
+) {
+    let Frontend {
+        video,
+        input,
+        audio,
+    } = frontend;
+    let rom_hash = machine.rom_hash();
+    let mut recording = config.record_path.as_ref().map(|_| {
+        Movie::new(
+            rom_hash,
+            machine.seed(),
+            config.quirks,
+            machine.instructions_per_frame(),
+            machine.cpu().rpl,
+        )
+    });
+
+    let mut pacer = Pacer::new(clock);
+    let mut fault = None;
+
+    let mut slot = 0;
+    let mut rewind = Rewind::new(config.rewind_seconds, rom_hash);
+    rewind.push(machine.cpu());
+    let mut debugger = Debugger::default();
+    // A movie plays through, there is no stopping it at the first frame
+    debugger.paused = config.debug && playback.is_none();
+    let prompt = config.debug.then(debugger::spawn_prompt);
+    if config.debug {
+        let location = debugger::location(machine.cpu());
+        video.message(&format!("{}\npaused at {}", debugger::HELP, location));
+    }
+
+    'running: loop {
+        let polled = input.poll(machine.frame_count());
+        let keys = match &playback {
+            Some(playback) => playback.keys(),
+            None => polled.keys,
+        };
+        // Keys only change between frames, as a movie records them
+        if !machine.mid_frame() {
+            machine.set_keys(keys);
+        }
+
+        // Jumping to another state would make a movie's input meaningless
+        let in_movie = recording.is_some() || playback.is_some();
+        let rewinding = polled.rewinding && !in_movie;
+
+        let mut commands = Vec::new();
+        match polled.hotkey {
+            Some(KeyStroke::Quit) => break 'running,
+            Some(KeyStroke::Mute) => audio.toggle_mute(),
+            // Pausing or stepping would leave the movie's input out of step
+            Some(KeyStroke::Pause | KeyStroke::Next | KeyStroke::StepOver | KeyStroke::StepOut)
+                if playback.is_some() =>
+            {
+                video.message("the debugger cannot stop a movie being played")
+            }
+            Some(KeyStroke::Pause) if debugger.paused => commands.push(DebugCommand::Continue),
+            Some(KeyStroke::Pause) => commands.push(DebugCommand::Pause),
+            Some(KeyStroke::Next) => commands.push(DebugCommand::Step),
+            Some(KeyStroke::StepOver) => commands.push(DebugCommand::StepOver),
+            Some(KeyStroke::StepOut) => commands.push(DebugCommand::StepOut),
+            Some(KeyStroke::SaveState) => save_state(machine, slot, video),
+            // Loading a state is a way out of a fault
+            Some(KeyStroke::LoadState) if in_movie => {
+                video.message("save states cannot be loaded during a movie")
+            }
+            Some(KeyStroke::LoadState) if load_state(machine, slot, video) => {
+                fault = None;
+                rewind.clear();
+                rewind.push(machine.cpu());
+            }
+            Some(KeyStroke::NextSlot) => {
+                slot = (slot + 1) % savestate::SLOTS;
+                video.message(&format!("save state slot {}", slot));
+            }
+            _ => {}
+        }
+        if let Some(prompt) = &prompt {
+            for line in prompt.try_iter().filter(|line| !line.trim().is_empty()) {
+                match DebugCommand::parse(&line) {
+                    Ok(command) => commands.push(command),
+                    Err(err) => video.message(&err),
+                }
+            }
+        }
+
+        for command in commands {
+            if command == DebugCommand::Quit {
+                break 'running;
+            }
+            if playback.is_some()
+                && matches!(
+                    command,
+                    DebugCommand::Pause
+                        | DebugCommand::Step
+                        | DebugCommand::StepOver
+                        | DebugCommand::StepOut
+                        | DebugCommand::RunTo(_)
+                        | DebugCommand::Break(_)
+                        | DebugCommand::Watch(_)
+                )
+            {
+                video.message("the debugger cannot stop a movie being played");
+                continue;
+            }
+            let output = debugger.apply(command, machine.cpu_mut());
+            if !output.is_empty() {
+                video.message(&output);
+            }
+        }
+
+        // A faulted CPU stays halted with the fault on screen until quit,
+        // or until rewound to before it
+        if rewinding {
+            if rewind.step_back(machine.cpu_mut()) {
+                fault = None;
+                machine.restart_frame();
+            }
+        } else if fault.is_none() && !debugger.paused {
+            let result = machine.run_frame_until(|cpu| {
+                if debugger.should_break(cpu) {
+                    return true;
+                }
+                if let Some(tracer) = &mut tracer {
+                    tracer.record(cpu);
+                }
+                false
+            });
+            match result {
+                Ok(true) => video.message(&debugger.break_reason(machine.cpu())),
+                Ok(false) => {
+                    rewind.push(machine.cpu());
+                    if let Some(movie) = &mut recording {
+                        movie.record(machine.cpu());
+                    }
+                    if let Some(playback) = &mut playback {
+                        if let Some(warning) = advance_playback(playback, machine.cpu()) {
+                            video.message(&warning);
+                        }
+                    }
+                }
+                Err(err) => {
+                    video.message(&format!("error: {}", err));
+                    if let Some(tracer) = &mut tracer {
+                        tracer.dump();
+                    }
+                    fault = Some(err);
+                }
+            }
+        }
+        if playback.as_ref().is_some_and(Playback::finished) {
+            video.message("movie finished, the keyboard is live again");
+            playback = None;
+        }
+        let running = fault.is_none() && !debugger.paused && !rewinding;
+        audio.update(running && machine.buzzer_on());
+        // The flags a movie starts from are its own, not the user's
+        if playback.is_some() {
+            machine.cpu_mut().rpl_dirty = false;
+        } else if let Err(err) = storage::flush_flags(machine.cpu_mut(), flags_path) {
+            video.message(&format!(
+                "could not write {}: {}",
+                flags_path.display(),
+                err
+            ));
+        }
+
+        if machine.exited() {
+            break 'running;
+        }
+
+        let status = match &fault {
+            Some(fault) => Some(format!("Halted: {}", fault)),
+            None if rewinding => Some(format!("Rewinding, {} frames left", rewind.frames())),
+            None if debugger.paused => Some(format!("Paused at {:#06x}", machine.cpu().pc)),
+            None => None,
+        };
+        video.present(machine.cpu(), status.as_deref());
+
+        pacer.wait_for_next_frame();
+    }
+
+    if let (Some(movie), Some(path)) = (&recording, &config.record_path) {
+        match movie.save(Path::new(path)) {
+            Ok(()) => video.message(&format!(
+                "recorded {} frames to {}",
+                movie.frames.len(),
+                path
+            )),
+            Err(err) => video.message(&format!("could not write {}: {}", path, err)),
+        }
+    }
+}
+
+fn save_state(machine: &Machine, slot: u8, video: &mut dyn VideoSink) {
+    let path = storage::state_path(machine.rom_hash(), slot);
+
+    match storage::save_state(&path, &machine.save_state()) {
+        Ok(()) => video.message(&format!("saved state to slot {}", slot)),
+        Err(err) => video.message(&format!("could not write {}: {}", path.display(), err)),
+    }
+}
+
+fn load_state(machine: &mut Machine, slot: u8, video: &mut dyn VideoSink) -> bool {
+    let path = storage::state_path(machine.rom_hash(), slot);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            video.message(&format!("could not read {}: {}", path.display(), err));
+            return false;
+        }
+    };
+
+    match machine.load_state(&bytes) {
+        Ok(()) => {
+            video.message(&format!("loaded state from slot {}", slot));
+            true
+        }
+        Err(err) => {
+            video.message(&format!("{}: {}", path.display(), err));
+            false
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use crate::{
+    audio::{AudioSink, RecordingAudio},
+    config::Config,
+    cpu::CPU,
+    debugger,
+    error::CpuFault,
+    keypad::{InputFrame, InputSource},
+    machine::Machine,
+    movie::{self, advance_playback, Playback},
+    storage,
+    trace::Tracer,
+};
+
+/// Keys to hold from given frames on, read from lines of `FRAME [KEY...]`
+/// with the keys in hex. A line with no keys releases them all.
+#[derive(Debug, PartialEq)]
+pub struct InputScript {
+    changes: Vec<(u64, [u8; 16])>,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut changes: Vec<(u64, [u8; 16])> = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |message: String| format!("line {}: {}", line_number + 1, message);
+
+            let mut words = line.split_whitespace();
+            let Some(frame) = words.next() else {
+                continue;
+            };
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| error(format!("expected a frame number, found '{}'", frame)))?;
+            if changes.last().is_some_and(|&(last, _)| frame <= last) {
+                return Err(error(format!(
+                    "frame {} is not after the line before",
+                    frame
+                )));
+            }
+
+            let mut keys = [0u8; 16];
+            for word in words {
+                let key = u8::from_str_radix(word, 16)
+                    .ok()
+                    .filter(|&key| key < 16)
+                    .ok_or_else(|| error(format!("'{}' is not a hex key", word)))?;
+                keys[key as usize] = 1;
+            }
+            changes.push((frame, keys));
+        }
+
+        Ok(InputScript { changes })
+    }
+
+    pub fn keys(&self, frame: u64) -> [u8; 16] {
+        self.changes
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start <= frame)
+            .map_or([0; 16], |&(_, keys)| keys)
+    }
+}
+
+impl InputSource for InputScript {
+    fn poll(&mut self, frame: u64) -> InputFrame {
+        InputFrame {
+            keys: self.keys(frame),
+            ..InputFrame::default()
+        }
+    }
+}
+
+/// Where a headless run gets its keypad input.
+pub enum Input {
+    None,
+    Script(InputScript),
+    Movie(Playback),
+}
+
+impl InputSource for Input {
+    fn poll(&mut self, frame: u64) -> InputFrame {
+        match self {
+            Input::None => InputFrame::default(),
+            Input::Script(script) => script.poll(frame),
+            Input::Movie(playback) => playback.poll(frame),
+        }
+    }
+}
+
+/// Runs `config.frames` frames, stopping early if the ROM exits or the
+/// `--until` condition is met, and prints the display, registers and state
+/// hash. They are still printed if the CPU faults.
+pub fn run(
+    machine: &mut Machine,
+    config: &Config,
+    flags_path: &Path,
+    tracer: &mut Option<Tracer>,
+    input: &mut Input,
+) -> Result<(), CpuFault> {
+    let mut audio = RecordingAudio::new(config.audio);
+
+    let mut result = Ok(());
+    let mut outcome = None;
+    while machine.frame_count() < config.frames && outcome.is_none() {
+        machine.set_keys(input.poll(machine.frame_count()).keys);
+
+        let frame = if tracer.is_none() && config.until.is_none() {
+            machine.run_frame().map(|_| false)
+        } else {
+            machine.run_frame_until(|cpu| {
+                if config.until.is_some_and(|until| until.hits(cpu)) {
+                    return true;
+                }
+                if let Some(tracer) = tracer {
+                    tracer.record(cpu);
+                }
+                false
+            })
+        };
+
+        let full_frame = matches!(frame, Ok(false));
+        match frame {
+            Ok(true) => outcome = config.until.map(|until| format!("reached {}", until)),
+            Ok(false) if machine.exited() => outcome = Some(String::from("exited")),
+            Ok(false) => {}
+            Err(fault) => {
+                if let Some(tracer) = tracer {
+                    tracer.dump();
+                }
+                outcome = Some(format!("halted: {}", fault));
+                result = Err(fault);
+            }
+        }
+        // A frame cut short by --until has not used up its input
+        if let Input::Movie(playback) = input {
+            if full_frame {
+                if let Some(warning) = advance_playback(playback, machine.cpu()) {
+                    eprintln!("{}", warning);
+                }
+            }
+            // The flags a movie starts from are its own, not the user's
+            machine.cpu_mut().rpl_dirty = false;
+        } else if let Err(err) = storage::flush_flags(machine.cpu_mut(), flags_path) {
+            eprintln!("could not write {}: {}", flags_path.display(), err);
+        }
+        audio.update(machine.buzzer_on());
+    }
+
+    if let Some(wav_path) = &config.wav_path {
+        if let Err(err) = std::fs::write(wav_path, audio.to_wav()) {
+            eprintln!("could not write {}: {}", wav_path, err);
+        }
+    }
+
+    let outcome = outcome.unwrap_or_else(|| String::from("frame limit"));
+    let frames = machine.frame_count();
+    println!(
+        "{}",
+        report(machine.cpu(), machine.rom_hash(), frames, &outcome)
+    );
+
+    result
+}
+
+/// The display as text, one character per pixel, then how the run ended,
+/// the registers and a hash of the whole machine state to compare runs by.
+pub fn report(cpu: &CPU, rom_hash: u64, frames: u64, outcome: &str) -> String {
+    let (width, height) = cpu.screen_size();
+    let mut lines: Vec<String> = cpu.display[..height]
+        .iter()
+        .map(|row| {
+            row[..width]
+                .iter()
+                .map(|&pixel| match pixel & 0b11 {
+                    0 => '.',
+                    1 => '#',
+                    2 => '+',
+                    _ => '@',
+                })
+                .collect()
+        })
+        .collect();
+
+    lines.push(format!("frames {}, {}", frames, outcome));
+    lines.push(debugger::registers(cpu));
+    lines.push(format!("hash {:016x}", movie::state_hash(cpu, rom_hash)));
+    lines.join("\n")
+}
@@ -0,0 +1,373 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction. Register operands
+/// are register numbers, not their values.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Instruction {
+    ScrollDown {
+        n: u8,
+    },
+    ScrollUp {
+        n: u8,
+    },
+    Cls,
+    Ret,
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    Jmp {
+        addr: u16,
+    },
+    Call {
+        addr: u16,
+    },
+    SeByte {
+        x: usize,
+        nn: u8,
+    },
+    SneByte {
+        x: usize,
+        nn: u8,
+    },
+    SeRegReg {
+        x: usize,
+        y: usize,
+    },
+    LdRangeToRam {
+        x: usize,
+        y: usize,
+    },
+    LdRamToRange {
+        x: usize,
+        y: usize,
+    },
+    SetRegToNn {
+        x: usize,
+        nn: u8,
+    },
+    AddValToReg {
+        x: usize,
+        nn: u8,
+    },
+    LdRegReg {
+        x: usize,
+        y: usize,
+    },
+    BitOr {
+        x: usize,
+        y: usize,
+    },
+    BitAnd {
+        x: usize,
+        y: usize,
+    },
+    BitXor {
+        x: usize,
+        y: usize,
+    },
+    AddRegReg {
+        x: usize,
+        y: usize,
+    },
+    SubRegReg {
+        x: usize,
+        y: usize,
+    },
+    Shr {
+        x: usize,
+        y: usize,
+    },
+    SubNotBorrow {
+        x: usize,
+        y: usize,
+    },
+    Shl {
+        x: usize,
+        y: usize,
+    },
+    SneRegReg {
+        x: usize,
+        y: usize,
+    },
+    SetIndexRegToAddr {
+        addr: u16,
+    },
+    /// Jumps to `addr` plus V0, or VX with the `jump_uses_vx` quirk.
+    JmpToAddrReg0 {
+        addr: u16,
+    },
+    RndNum {
+        x: usize,
+        nn: u8,
+    },
+    Draw {
+        x: usize,
+        y: usize,
+        n: u8,
+    },
+    Skp {
+        x: usize,
+    },
+    Sknp {
+        x: usize,
+    },
+    /// F000 NNNN, the address is the word after the instruction.
+    LdLongIndex,
+    SelectPlanes {
+        x: usize,
+    },
+    LdAudioPattern,
+    LdDtToReg {
+        x: usize,
+    },
+    LdKey {
+        x: usize,
+    },
+    LdRegToDt {
+        x: usize,
+    },
+    LdStToReg {
+        x: usize,
+    },
+    AddIToReg {
+        x: usize,
+    },
+    LdFont {
+        x: usize,
+    },
+    LdBigFont {
+        x: usize,
+    },
+    LdPitch {
+        x: usize,
+    },
+    Bcd {
+        x: usize,
+    },
+    LdRegToRam {
+        x: usize,
+    },
+    LdRamToReg {
+        x: usize,
+    },
+    LdRegToRpl {
+        x: usize,
+    },
+    LdRplToReg {
+        x: usize,
+    },
+    Invalid(u16),
+}
+
+impl Instruction {
+    pub fn decode(inst: u16) -> Instruction {
+        let opcode = ((inst & 0xF000) >> 12) as u8;
+        let x = ((inst & 0x0F00) >> 8) as usize;
+        let y = ((inst & 0x00F0) >> 4) as usize;
+        let n = (inst & 0x000F) as u8;
+        let nn = (inst & 0x00FF) as u8;
+        let addr = inst & 0x0FFF;
+
+        match opcode {
+            0x0 if x == 0 => match nn {
+                0xC0..=0xCF => Instruction::ScrollDown { n },
+                0xD0..=0xDF => Instruction::ScrollUp { n },
+                0xE0 => Instruction::Cls,
+                0xEE => Instruction::Ret,
+                0xFB => Instruction::ScrollRight,
+                0xFC => Instruction::ScrollLeft,
+                0xFD => Instruction::Exit,
+                0xFE => Instruction::LowRes,
+                0xFF => Instruction::HighRes,
+                _ => Instruction::Invalid(inst),
+            },
+            0x1 => Instruction::Jmp { addr },
+            0x2 => Instruction::Call { addr },
+            0x3 => Instruction::SeByte { x, nn },
+            0x4 => Instruction::SneByte { x, nn },
+            0x5 => match n {
+                0x0 => Instruction::SeRegReg { x, y },
+                0x2 => Instruction::LdRangeToRam { x, y },
+                0x3 => Instruction::LdRamToRange { x, y },
+                _ => Instruction::Invalid(inst),
+            },
+            0x6 => Instruction::SetRegToNn { x, nn },
+            0x7 => Instruction::AddValToReg { x, nn },
+            0x8 => match n {
+                0x0 => Instruction::LdRegReg { x, y },
+                0x1 => Instruction::BitOr { x, y },
+                0x2 => Instruction::BitAnd { x, y },
+                0x3 => Instruction::BitXor { x, y },
+                0x4 => Instruction::AddRegReg { x, y },
+                0x5 => Instruction::SubRegReg { x, y },
+                0x6 => Instruction::Shr { x, y },
+                0x7 => Instruction::SubNotBorrow { x, y },
+                0xE => Instruction::Shl { x, y },
+                _ => Instruction::Invalid(inst),
+            },
+            0x9 if n == 0 => Instruction::SneRegReg { x, y },
+            0xA => Instruction::SetIndexRegToAddr { addr },
+            0xB => Instruction::JmpToAddrReg0 { addr },
+            0xC => Instruction::RndNum { x, nn },
+            0xD => Instruction::Draw { x, y, n },
+            0xE => match nn {
+                0x9E => Instruction::Skp { x },
+                0xA1 => Instruction::Sknp { x },
+                _ => Instruction::Invalid(inst),
+            },
+            0xF => match nn {
+                0x00 if x == 0 => Instruction::LdLongIndex,
+                0x01 => Instruction::SelectPlanes { x },
+                0x02 if x == 0 => Instruction::LdAudioPattern,
+                0x07 => Instruction::LdDtToReg { x },
+                0x0A => Instruction::LdKey { x },
+                0x15 => Instruction::LdRegToDt { x },
+                0x18 => Instruction::LdStToReg { x },
+                0x1E => Instruction::AddIToReg { x },
+                0x29 => Instruction::LdFont { x },
+                0x30 => Instruction::LdBigFont { x },
+                0x33 => Instruction::Bcd { x },
+                0x3A => Instruction::LdPitch { x },
+                0x55 => Instruction::LdRegToRam { x },
+                0x65 => Instruction::LdRamToReg { x },
+                0x75 => Instruction::LdRegToRpl { x },
+                0x85 => Instruction::LdRplToReg { x },
+                _ => Instruction::Invalid(inst),
+            },
+            _ => Instruction::Invalid(inst),
+        }
+    }
+
+    /// The opcode `decode` turns back into this instruction. The long index
+    /// load's address is not included.
+    pub fn encode(&self) -> u16 {
+        let xy =
+            |op: u16, x: usize, y: usize, n: u16| op << 12 | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |op: u16, x: usize, nn: u8| op << 12 | (x as u16) << 8 | nn as u16;
+        let fx = |x: usize, nn: u16| 0xF000 | (x as u16) << 8 | nn;
+
+        match *self {
+            Instruction::ScrollDown { n } => 0x00C0 | n as u16,
+            Instruction::ScrollUp { n } => 0x00D0 | n as u16,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jmp { addr } => 0x1000 | addr,
+            Instruction::Call { addr } => 0x2000 | addr,
+            Instruction::SeByte { x, nn } => xnn(0x3, x, nn),
+            Instruction::SneByte { x, nn } => xnn(0x4, x, nn),
+            Instruction::SeRegReg { x, y } => xy(0x5, x, y, 0x0),
+            Instruction::LdRangeToRam { x, y } => xy(0x5, x, y, 0x2),
+            Instruction::LdRamToRange { x, y } => xy(0x5, x, y, 0x3),
+            Instruction::SetRegToNn { x, nn } => xnn(0x6, x, nn),
+            Instruction::AddValToReg { x, nn } => xnn(0x7, x, nn),
+            Instruction::LdRegReg { x, y } => xy(0x8, x, y, 0x0),
+            Instruction::BitOr { x, y } => xy(0x8, x, y, 0x1),
+            Instruction::BitAnd { x, y } => xy(0x8, x, y, 0x2),
+            Instruction::BitXor { x, y } => xy(0x8, x, y, 0x3),
+            Instruction::AddRegReg { x, y } => xy(0x8, x, y, 0x4),
+            Instruction::SubRegReg { x, y } => xy(0x8, x, y, 0x5),
+            Instruction::Shr { x, y } => xy(0x8, x, y, 0x6),
+            Instruction::SubNotBorrow { x, y } => xy(0x8, x, y, 0x7),
+            Instruction::Shl { x, y } => xy(0x8, x, y, 0xE),
+            Instruction::SneRegReg { x, y } => xy(0x9, x, y, 0x0),
+            Instruction::SetIndexRegToAddr { addr } => 0xA000 | addr,
+            Instruction::JmpToAddrReg0 { addr } => 0xB000 | addr,
+            Instruction::RndNum { x, nn } => xnn(0xC, x, nn),
+            Instruction::Draw { x, y, n } => xy(0xD, x, y, n as u16),
+            Instruction::Skp { x } => xnn(0xE, x, 0x9E),
+            Instruction::Sknp { x } => xnn(0xE, x, 0xA1),
+            Instruction::LdLongIndex => 0xF000,
+            Instruction::SelectPlanes { x } => fx(x, 0x01),
+            Instruction::LdAudioPattern => 0xF002,
+            Instruction::LdDtToReg { x } => fx(x, 0x07),
+            Instruction::LdKey { x } => fx(x, 0x0A),
+            Instruction::LdRegToDt { x } => fx(x, 0x15),
+            Instruction::LdStToReg { x } => fx(x, 0x18),
+            Instruction::AddIToReg { x } => fx(x, 0x1E),
+            Instruction::LdFont { x } => fx(x, 0x29),
+            Instruction::LdBigFont { x } => fx(x, 0x30),
+            Instruction::Bcd { x } => fx(x, 0x33),
+            Instruction::LdPitch { x } => fx(x, 0x3A),
+            Instruction::LdRegToRam { x } => fx(x, 0x55),
+            Instruction::LdRamToReg { x } => fx(x, 0x65),
+            Instruction::LdRegToRpl { x } => fx(x, 0x75),
+            Instruction::LdRplToReg { x } => fx(x, 0x85),
+            Instruction::Invalid(inst) => inst,
+        }
+    }
+
+    /// Length in bytes, only the XO-CHIP long index load is four.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdLongIndex => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Mnemonics follow Cowgod's CHIP-8 reference, with the usual SUPER-CHIP
+/// and XO-CHIP extensions.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jmp { addr } => write!(f, "JP {:#05X}", addr),
+            Instruction::Call { addr } => write!(f, "CALL {:#05X}", addr),
+            Instruction::SeByte { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SneByte { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SeRegReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdRangeToRam { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LdRamToRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::SetRegToNn { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddValToReg { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::LdRegReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::BitOr { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::BitAnd { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::BitXor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubRegReg { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubNotBorrow { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneRegReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIndexRegToAddr { addr } => write!(f, "LD I, {:#05X}", addr),
+            Instruction::JmpToAddrReg0 { addr } => write!(f, "JP V0, {:#05X}", addr),
+            Instruction::RndNum { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp { x } => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LdLongIndex => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes { x } => write!(f, "PLANE {}", x),
+            Instruction::LdAudioPattern => write!(f, "AUDIO"),
+            Instruction::LdDtToReg { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::LdRegToDt { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStToReg { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIToReg { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFont { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LdBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdPitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::LdRegToRam { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdRamToReg { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRegToRpl { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LdRplToReg { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::Invalid(inst) => write!(f, "DW {:#06X}", inst),
+        }
+    }
+}
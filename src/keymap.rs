@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+pub const LAYOUTS: [&str; 6] = ["qwerty", "azerty", "qwertz", "dvorak", "colemak", "arrows"];
+
+/// Hex keys in the order they sit on the COSMAC VIP keypad, left to right
+/// and top to bottom.
+const KEYPAD_GRID: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Physical keys covering the same block of the keyboard in each layout,
+/// in `KEYPAD_GRID` order. Keys SDL has no keycode for, like the AZERTY
+/// "é", are reported by the name of the key in the same place on a US
+/// keyboard.
+fn layout_keys(layout: &str) -> Option<[&'static str; 16]> {
+    match layout {
+        "qwerty" | "arrows" => Some([
+            "1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "z", "x", "c", "v",
+        ]),
+        "azerty" => Some([
+            "&", "2", "\"", "'", "a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v",
+        ]),
+        "qwertz" => Some([
+            "1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "y", "x", "c", "v",
+        ]),
+        "dvorak" => Some([
+            "1", "2", "3", "4", "'", ",", ".", "p", "a", "o", "e", "u", ";", "q", "j", "k",
+        ]),
+        "colemak" => Some([
+            "1", "2", "3", "4", "q", "w", "f", "p", "a", "r", "s", "t", "z", "x", "c", "v",
+        ]),
+        _ => None,
+    }
+}
+
+/// Which physical keys, by name, press each hex key. Several keys may press
+/// the same hex key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    bindings: HashMap<String, u8>,
+}
+
+impl KeyMap {
+    pub fn preset(layout: &str) -> Option<KeyMap> {
+        let keys = layout_keys(layout)?;
+        let mut bindings: HashMap<String, u8> = keys
+            .iter()
+            .zip(KEYPAD_GRID.iter())
+            .map(|(name, &hex)| (name.to_string(), hex))
+            .collect();
+
+        if layout == "arrows" {
+            // The arrows sit on the same hex keys as WASD
+            bindings.insert(String::from("up"), 0x5);
+            bindings.insert(String::from("left"), 0x7);
+            bindings.insert(String::from("down"), 0x8);
+            bindings.insert(String::from("right"), 0x9);
+        }
+
+        Some(KeyMap { bindings })
+    }
+
+    /// Replaces every key bound to `hex` with `keys`.
+    pub fn bind(&mut self, hex: u8, keys: &[&str]) {
+        self.bindings.retain(|_, bound| *bound != hex);
+        for key in keys {
+            self.bindings.insert(key.to_lowercase(), hex);
+        }
+    }
+
+    pub fn hex_key(&self, name: &str) -> Option<usize> {
+        self.bindings.get(name).map(|&hex| hex as usize)
+    }
+
+    /// Every bound key name, sorted.
+    #[cfg(feature = "terminal")]
+    pub fn key_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.bindings.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Builds the key map for `rom_name` from a key map file.
+    ///
+    /// ```text
+    /// # Lines before any section apply to every ROM
+    /// layout = dvorak
+    /// 5 = w up
+    ///
+    /// [breakout.ch8]
+    /// 4 = left
+    /// 6 = right
+    /// ```
+    ///
+    /// `layout` picks the preset the bindings start from, `layout_override`
+    /// takes precedence over it. A hex key line replaces that key's bindings.
+    pub fn from_config(
+        text: &str,
+        rom_name: &str,
+        layout_override: Option<&str>,
+    ) -> Result<KeyMap, String> {
+        let mut layout = None;
+        let mut bindings = Vec::new();
+        let mut in_rom_section = true;
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |message: String| format!("line {}: {}", line_number + 1, message);
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[') {
+                let section = section
+                    .strip_suffix(']')
+                    .ok_or_else(|| error(String::from("missing ']'")))?;
+                in_rom_section = section.trim() == rom_name;
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected '=' in '{}'", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            if !in_rom_section {
+                continue;
+            }
+
+            if key == "layout" {
+                if layout_keys(value).is_none() {
+                    return Err(error(format!("unknown layout '{}'", value)));
+                }
+                layout = Some(value.to_string());
+            } else {
+                let hex = u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&hex| hex < 16)
+                    .ok_or_else(|| error(format!("'{}' is not a hex key", key)))?;
+                bindings.push((hex, value.split_whitespace().collect::<Vec<_>>()));
+            }
+        }
+
+        let layout = layout_override.or(layout.as_deref()).unwrap_or("qwerty");
+        let mut keymap =
+            KeyMap::preset(layout).ok_or_else(|| format!("unknown layout '{}'", layout))?;
+        for (hex, keys) in bindings {
+            keymap.bind(hex, &keys);
+        }
+
+        Ok(keymap)
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::preset("qwerty").unwrap()
+    }
+}
@@ -1,147 +1,54 @@
-use sdl2::{event::Event, keyboard::Keycode, EventPump};
-
-use crate::cpu::CPU;
+use std::collections::VecDeque;
 
 #[derive(Debug, PartialEq)]
 pub enum KeyStroke {
     Quit,
     Key([u8; 16]),
     Next,
+    Mute,
+    Pause,
+    StepOver,
+    StepOut,
+    SaveState,
+    LoadState,
+    NextSlot,
 }
 
-pub fn check_for_key_press(event_pump: &mut EventPump, cpu: &mut CPU) -> KeyStroke {
-    let mut key_pressed = [0u8; 16];
+/// What a front end reads from its input device once per loop.
+#[derive(Debug, Default, PartialEq)]
+pub struct InputFrame {
+    /// Hex keys held, 1 for pressed.
+    pub keys: [u8; 16],
+    /// Emulator hotkey pressed since the last poll, never `KeyStroke::Key`.
+    pub hotkey: Option<KeyStroke>,
+    /// The rewind hotkey is held.
+    pub rewinding: bool,
+}
 
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => return KeyStroke::Quit,
-            Event::KeyDown {
-                keycode: Some(Keycode::Num1),
-                ..
-            } => {
-                key_pressed[0] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::Num2),
-                ..
-            } => {
-                key_pressed[1] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::Num3),
-                ..
-            } => {
-                key_pressed[2] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::Num4),
-                ..
-            } => {
-                key_pressed[3] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::Q),
-                ..
-            } => {
-                key_pressed[4] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::W),
-                ..
-            } => {
-                key_pressed[5] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::E),
-                ..
-            } => {
-                key_pressed[6] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::R),
-                ..
-            } => {
-                key_pressed[7] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } => {
-                key_pressed[8] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => {
-                key_pressed[9] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
-            } => {
-                key_pressed[10] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::F),
-                ..
-            } => {
-                key_pressed[11] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::Z),
-                ..
-            } => {
-                key_pressed[12] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::X),
-                ..
-            } => {
-                key_pressed[13] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::C),
-                ..
-            } => {
-                key_pressed[14] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::V),
-                ..
-            } => {
-                key_pressed[15] = 1;
-                cpu.key_pressed = true;
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::Space),
-                ..
-            } => return KeyStroke::Next,
-            _ => {}
+/// Supplies keypad input and hotkeys, `frame` being the number of frames
+/// the CPU has run so far.
+pub trait InputSource {
+    fn poll(&mut self, frame: u64) -> InputFrame;
+}
+
+/// Hands out queued input one poll at a time, then quits once it runs out.
+pub struct QueuedInput {
+    frames: VecDeque<InputFrame>,
+}
+
+impl QueuedInput {
+    pub fn new(frames: Vec<InputFrame>) -> QueuedInput {
+        QueuedInput {
+            frames: frames.into(),
         }
     }
-    if cpu.key_pressed {
-        cpu.set_key(&KeyStroke::Key(key_pressed));
-        cpu.key_pressed = false;
-    }
+}
 
-    KeyStroke::Key(key_pressed)
+impl InputSource for QueuedInput {
+    fn poll(&mut self, _frame: u64) -> InputFrame {
+        self.frames.pop_front().unwrap_or(InputFrame {
+            hotkey: Some(KeyStroke::Quit),
+            ..InputFrame::default()
+        })
+    }
 }
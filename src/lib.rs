@@ -0,0 +1,55 @@
+//! The Rusteight emulator core: a CHIP-8, SUPER-CHIP and XO-CHIP machine
+//! with no window of its own. Load a [`Rom`], build a [`Machine`] with the
+//! [`Quirks`] of the platform it targets and run it a frame at a time,
+//! drawing through a [`VideoSink`] and reading keys from an [`InputSource`].
+
+// Built without a frontend, the debugger and the rest of the interactive
+// pieces only run in the tests
+#![cfg_attr(not(any(feature = "sdl", feature = "terminal")), allow(dead_code))]
+
+mod assembler;
+mod audio;
+#[cfg_attr(not(feature = "terminal"), allow(dead_code))]
+mod cells;
+#[doc(hidden)]
+pub mod cli;
+mod config;
+mod cpu;
+mod debugger;
+mod disassembler;
+mod error;
+mod frontend;
+mod headless;
+mod instruction;
+mod keymap;
+mod keypad;
+mod machine;
+mod movie;
+mod quirks;
+mod rewind;
+mod rng;
+mod rom_loader;
+mod savestate;
+mod scheduler;
+mod storage;
+#[cfg(feature = "terminal")]
+mod terminal;
+mod tests;
+mod trace;
+mod video;
+#[cfg(feature = "sdl")]
+mod window_manager;
+#[cfg(feature = "sdl")]
+mod windowed;
+
+pub use assembler::AssembleError;
+pub use audio::{AudioSink, NullAudio};
+pub use cpu::{Display, CPU, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+pub use error::{CpuFault, FaultKind, FaultMode, StartupError};
+pub use instruction::Instruction;
+pub use keypad::{InputFrame, InputSource, KeyStroke, QueuedInput};
+pub use machine::Machine;
+pub use quirks::{QuirkProfile, Quirks};
+pub use rom_loader::Rom;
+pub use savestate::StateError;
+pub use video::{MemoryVideo, VideoSink};
@@ -0,0 +1,170 @@
+use crate::{
+    cpu::{Display, CPU},
+    error::{CpuFault, FaultMode},
+    instruction::Instruction,
+    keypad::KeyStroke,
+    quirks::Quirks,
+    rng::Rng,
+    rom_loader::{self, Rom},
+    savestate::{self, StateError},
+    scheduler,
+};
+
+/// A CHIP-8 machine with a ROM loaded, run a frame at a time by whatever
+/// front end embeds it. Pacing frames at 60 Hz is left to the caller.
+pub struct Machine {
+    cpu: CPU,
+    rom_hash: u64,
+    seed: u64,
+    instructions_per_frame: u32,
+    /// Instructions left in a frame `run_frame_until` stopped early, so
+    /// resuming it ends on the same boundary as an uninterrupted run.
+    frame_left: Option<u32>,
+    frame_count: u64,
+}
+
+impl Machine {
+    /// Seeded with 0 and running 10 instructions per frame until changed.
+    pub fn new(rom: &Rom, quirks: Quirks) -> Machine {
+        let mut cpu = CPU::init_cpu();
+        cpu.ram = rom.memory();
+        cpu.quirks = quirks;
+        cpu.memory_size = rom_loader::memory_size(rom.profile());
+
+        Machine {
+            cpu,
+            rom_hash: rom.hash(),
+            seed: 0,
+            instructions_per_frame: 10,
+            frame_left: None,
+            frame_count: 0,
+        }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.cpu.rng = Rng::new(seed);
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_fault_mode(&mut self, fault_mode: FaultMode) {
+        self.cpu.fault_mode = fault_mode;
+    }
+
+    /// The SUPER-CHIP flag registers, kept between runs of the ROM.
+    pub fn set_flags(&mut self, flags: [u8; 16]) {
+        self.cpu.rpl = flags;
+    }
+
+    /// Keys held for the coming frames, 1 for pressed, indexed by hex key.
+    pub fn set_keys(&mut self, keys: [u8; 16]) {
+        self.cpu.set_key(&KeyStroke::Key(keys));
+    }
+
+    /// Runs one 1/60 s frame of instructions and ticks the timers.
+    pub fn run_frame(&mut self) -> Result<(), CpuFault> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    /// Runs one frame, checking `stop` before every instruction, such as a
+    /// debugger's breakpoints or a tracer. A frame stopped early leaves the
+    /// timers alone and is not counted, returning `Ok(true)`. The next call
+    /// finishes it with only the instructions it had left.
+    pub fn run_frame_until<F: FnMut(&CPU) -> bool>(&mut self, stop: F) -> Result<bool, CpuFault> {
+        let mut budget = self
+            .frame_left
+            .take()
+            .unwrap_or(self.instructions_per_frame);
+        let result = scheduler::run_frame_until(&mut self.cpu, &mut budget, stop);
+        match result {
+            Ok(false) => self.frame_count += 1,
+            _ => self.frame_left = Some(budget),
+        }
+        result
+    }
+
+    /// A frame was stopped early and has not finished yet. Keys set now
+    /// would change the keys it started with.
+    pub fn mid_frame(&self) -> bool {
+        self.frame_left.is_some()
+    }
+
+    /// Drops what is left of a frame stopped early, once the CPU has been
+    /// put back on a frame boundary.
+    pub(crate) fn restart_frame(&mut self) {
+        self.frame_left = None;
+    }
+
+    /// Executes a single instruction, leaving the timers alone.
+    pub fn step(&mut self) -> Result<Instruction, CpuFault> {
+        self.cpu.tick()
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.cpu.display
+    }
+
+    /// Width and height of the part of `display` in use.
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.cpu.screen_size()
+    }
+
+    pub fn buzzer_on(&self) -> bool {
+        self.cpu.sound_timer > 0
+    }
+
+    /// The ROM ran the SUPER-CHIP exit instruction.
+    pub fn exited(&self) -> bool {
+        self.cpu.exited
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Identifies the ROM, save states only load into a machine running
+    /// the same one.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// The seed the random number generator started from, as a movie
+    /// records it.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save(&self.cpu, self.rom_hash)
+    }
+
+    /// A frame stopped early is dropped, the state starts a fresh one.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        savestate::restore(&mut self.cpu, bytes, self.rom_hash)?;
+        self.frame_left = None;
+        Ok(())
+    }
+
+    /// Hash of the whole machine state, equal for two runs only if they
+    /// have not diverged.
+    pub fn state_hash(&self) -> u64 {
+        savestate::hash(&self.save_state())
+    }
+
+    /// Registers, memory and the rest, for tools that need more than the
+    /// methods above.
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub(crate) fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fmt::Write as _, io, path::Path};
+
+use crate::{
+    cpu::CPU,
+    keypad::{InputFrame, InputSource},
+    quirks::QuirkProfile,
+    savestate,
+};
+
+const HEADER: &str = "rusteight movie 1";
+
+/// Frames between the state hashes checked on playback.
+pub const CHECK_INTERVAL: usize = 60;
+
+/// Keypad input for every frame of a run, with everything else needed to
+/// start the run the same way.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub quirks: QuirkProfile,
+    pub instructions_per_frame: u32,
+    pub flags: [u8; 16],
+    pub frames: Vec<[u8; 16]>,
+    /// Hash of the machine state after each `CHECK_INTERVAL` frames.
+    pub checks: Vec<u64>,
+}
+
+impl Movie {
+    pub fn new(
+        rom_hash: u64,
+        seed: u64,
+        quirks: QuirkProfile,
+        instructions_per_frame: u32,
+        flags: [u8; 16],
+    ) -> Movie {
+        Movie {
+            rom_hash,
+            seed,
+            quirks,
+            instructions_per_frame,
+            flags,
+            frames: Vec::new(),
+            checks: Vec::new(),
+        }
+    }
+
+    /// Adds a frame that just ran with the keys held in `cpu.keypad`.
+    pub fn record(&mut self, cpu: &CPU) {
+        self.frames.push(cpu.keypad);
+        if self.frames.len().is_multiple_of(CHECK_INTERVAL) {
+            self.checks.push(state_hash(cpu, self.rom_hash));
+        }
+    }
+
+    /// A header of `name value` lines, then one line per frame of the held
+    /// keys as a hex bit mask, key 0 in the lowest bit. A `check` line
+    /// follows every `CHECK_INTERVAL` frames.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{}\nrom {:016x}\nseed {}\nquirks {}\nipf {}\nflags {}\n",
+            HEADER,
+            self.rom_hash,
+            self.seed,
+            self.quirks.name(),
+            self.instructions_per_frame,
+            self.flags
+                .iter()
+                .map(|flag| format!("{:02x}", flag))
+                .collect::<String>()
+        );
+
+        for (frame, keys) in self.frames.iter().enumerate() {
+            let mask = (0..16).fold(0u16, |mask, key| mask | ((keys[key] != 0) as u16) << key);
+            writeln!(text, "{:04x}", mask).unwrap();
+            if (frame + 1).is_multiple_of(CHECK_INTERVAL) {
+                if let Some(check) = self.checks.get((frame + 1) / CHECK_INTERVAL - 1) {
+                    writeln!(text, "check {:016x}", check).unwrap();
+                }
+            }
+        }
+
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err(String::from("not a rusteight movie"));
+        }
+
+        let mut header = |name: &str| -> Result<String, String> {
+            let (line_number, line) = lines.next().ok_or(format!("missing '{}' line", name))?;
+            line.trim()
+                .strip_prefix(name)
+                .and_then(|value| value.strip_prefix(' '))
+                .map(str::to_string)
+                .ok_or(format!("line {}: expected '{}'", line_number + 1, name))
+        };
+
+        let rom_hash = header("rom")?;
+        let seed = header("seed")?;
+        let quirks = header("quirks")?;
+        let ipf = header("ipf")?;
+        let flags = header("flags")?;
+        let invalid = |name: &str, value: &str| format!("invalid {} '{}'", name, value);
+
+        let mut movie = Movie::new(
+            u64::from_str_radix(&rom_hash, 16).map_err(|_| invalid("ROM hash", &rom_hash))?,
+            seed.parse().map_err(|_| invalid("seed", &seed))?,
+            QuirkProfile::from_name(&quirks).ok_or(invalid("quirk profile", &quirks))?,
+            ipf.parse().map_err(|_| invalid("ipf", &ipf))?,
+            parse_flags(&flags).ok_or(invalid("flags", &flags))?,
+        );
+
+        for (line_number, line) in lines {
+            let line = line.trim();
+            let error = |message: String| format!("line {}: {}", line_number + 1, message);
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(hash) = line.strip_prefix("check ") {
+                let hash = u64::from_str_radix(hash, 16)
+                    .map_err(|_| error(invalid("state hash", hash)))?;
+                movie.checks.push(hash);
+            } else {
+                let mask =
+                    u16::from_str_radix(line, 16).map_err(|_| error(invalid("key mask", line)))?;
+                movie
+                    .frames
+                    .push(std::array::from_fn(|key| (mask >> key) as u8 & 1));
+            }
+        }
+
+        Ok(movie)
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Movie::parse(&text)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+}
+
+fn parse_flags(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut flags = [0u8; 16];
+    for (flag, pair) in flags.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *flag = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(flags)
+}
+
+/// Hash of the whole machine, as a save state would store it.
+pub fn state_hash(cpu: &CPU, rom_hash: u64) -> u64 {
+    savestate::hash(&savestate::save(cpu, rom_hash))
+}
+
+/// Feeds a movie's input back one frame at a time.
+pub struct Playback {
+    movie: Movie,
+    frame: usize,
+    desynced: bool,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Playback {
+        Playback {
+            movie,
+            frame: 0,
+            desynced: false,
+        }
+    }
+
+    /// Keys held for the next frame, none once the movie is over.
+    pub fn keys(&self) -> [u8; 16] {
+        self.movie
+            .frames
+            .get(self.frame)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Moves on after a frame has run. Returns the frame count of the first
+    /// check that does not match, only once since everything after it will
+    /// differ too.
+    pub fn advance(&mut self, cpu: &CPU) -> Result<(), usize> {
+        self.frame += 1;
+        if self.desynced || !self.frame.is_multiple_of(CHECK_INTERVAL) {
+            return Ok(());
+        }
+
+        match self.movie.checks.get(self.frame / CHECK_INTERVAL - 1) {
+            Some(&expected) if expected != state_hash(cpu, self.movie.rom_hash) => {
+                self.desynced = true;
+                Err(self.frame)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl InputSource for Playback {
+    fn poll(&mut self, _frame: u64) -> InputFrame {
+        InputFrame {
+            keys: self.keys(),
+            ..InputFrame::default()
+        }
+    }
+}
+
+/// Advances `playback`, returning a warning if it has desynced.
+pub fn advance_playback(playback: &mut Playback, cpu: &CPU) -> Option<String> {
+    playback.advance(cpu).err().map(|frame| {
+        format!(
+            "movie desynced: state after frame {} does not match the recording",
+            frame
+        )
+    })
+}
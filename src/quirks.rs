@@ -0,0 +1,84 @@
+/// Behaviour of the opcodes that CHIP-8 interpreters disagree on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Quirks {
+    /// 8XY6/8XYE copy VY into VX before shifting.
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing past the last register transferred.
+    pub load_store_increments_i: bool,
+    /// BNNN jumps to NNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 clear VF.
+    pub vf_reset: bool,
+    /// Sprites are cut off at the screen edge instead of wrapping around.
+    pub clip_sprites: bool,
+    /// DXYN waits for the next frame before execution continues.
+    pub display_wait: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QuirkProfile {
+    CosmacVip,
+    Chip48,
+    Schip,
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub fn from_name(name: &str) -> Option<QuirkProfile> {
+        match name {
+            "vip" | "chip8" => Some(QuirkProfile::CosmacVip),
+            "chip48" => Some(QuirkProfile::Chip48),
+            "schip" => Some(QuirkProfile::Schip),
+            "xochip" => Some(QuirkProfile::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuirkProfile::CosmacVip => "vip",
+            QuirkProfile::Chip48 => "chip48",
+            QuirkProfile::Schip => "schip",
+            QuirkProfile::XoChip => "xochip",
+        }
+    }
+}
+
+impl Quirks {
+    pub fn preset(profile: QuirkProfile) -> Quirks {
+        match profile {
+            QuirkProfile::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                vf_reset: true,
+                clip_sprites: true,
+                display_wait: true,
+            },
+            QuirkProfile::Chip48 => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+            },
+            QuirkProfile::Schip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+            },
+            QuirkProfile::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                vf_reset: false,
+                clip_sprites: false,
+                display_wait: false,
+            },
+        }
+    }
+}
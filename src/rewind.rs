@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+
+use crate::{cpu::CPU, savestate};
+
+/// Zero bytes in a row that end a literal run in a delta. Shorter gaps
+/// cost more as a new run than as literals.
+const MIN_ZERO_RUN: usize = 4;
+
+/// Save states of the last few seconds, one per frame. Only the newest is
+/// kept whole, each older one is stored as the compressed difference from
+/// the one after it.
+pub struct Rewind {
+    rom_hash: u64,
+    capacity: usize,
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(seconds: u32, rom_hash: u64) -> Rewind {
+        Rewind {
+            rom_hash,
+            capacity: seconds as usize * 60,
+            current: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Records the state at the end of a frame.
+    pub fn push(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = savestate::save(cpu, self.rom_hash);
+        if let Some(previous) = self.current.take() {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode_delta(&state, &previous));
+        }
+        self.current = Some(state);
+    }
+
+    /// Goes back one frame, returning false once there are none left.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        let (Some(current), Some(delta)) = (self.current.as_mut(), self.deltas.pop_back()) else {
+            return false;
+        };
+
+        apply_delta(current, &delta);
+        savestate::restore(cpu, current, self.rom_hash).is_ok()
+    }
+
+    /// Forgets every frame, for when the state jumps such as on loading a
+    /// save state.
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+    }
+
+    /// How many frames back can be rewound.
+    pub fn frames(&self) -> usize {
+        self.deltas.len()
+    }
+}
+
+/// XORs two equal length states and run-length encodes the result as
+/// pairs of a count of zero bytes then a count of literal bytes followed
+/// by the literals.
+pub fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = from.iter().zip(to).map(|(a, b)| a ^ b).collect();
+    let mut delta = Vec::new();
+    let mut pos = 0;
+
+    while pos < xor.len() {
+        let zeros = xor[pos..].iter().take_while(|&&byte| byte == 0).count();
+        pos += zeros;
+
+        let start = pos;
+        while pos < xor.len() {
+            let gap = xor[pos..]
+                .iter()
+                .take(MIN_ZERO_RUN)
+                .take_while(|&&byte| byte == 0)
+                .count();
+            if gap == MIN_ZERO_RUN || pos + gap == xor.len() {
+                break;
+            }
+            pos += gap.max(1);
+        }
+
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, pos - start);
+        delta.extend_from_slice(&xor[start..pos]);
+    }
+
+    delta
+}
+
+pub fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut read = 0;
+
+    while read < delta.len() {
+        pos += read_varint(delta, &mut read);
+        let len = read_varint(delta, &mut read);
+        for (byte, change) in state[pos..pos + len]
+            .iter_mut()
+            .zip(&delta[read..read + len])
+        {
+            *byte ^= change;
+        }
+        pos += len;
+        read += len;
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
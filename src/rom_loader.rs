@@ -1,4 +1,6 @@
-use std::{fs::File, io::Read};
+use std::{fs, io::ErrorKind, path::Path};
+
+use crate::{assembler, cpu::RAM_SIZE, error::StartupError, quirks::QuirkProfile, savestate};
 
 pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -19,31 +21,136 @@ pub const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
-pub fn load_rom(path: String) -> [u8; 4096] {
-    let mut rom = File::open(path).unwrap();
-    let mut buf = [0u8; 3584];
-    rom.read(&mut buf).unwrap();
+pub const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+pub const FONT_ADDR: usize = 0x32;
+pub const BIG_FONT_ADDR: usize = FONT_ADDR + FONT.len();
+
+/// Memory of the platform `profile` targets. Only XO-CHIP has more than
+/// the original 4 KiB.
+pub fn memory_size(profile: QuirkProfile) -> usize {
+    match profile {
+        QuirkProfile::XoChip => RAM_SIZE,
+        QuirkProfile::CosmacVip | QuirkProfile::Chip48 | QuirkProfile::Schip => 0x1000,
+    }
+}
+
+/// Largest program that fits in the memory of the platform `profile`
+/// targets, after the 0x200 bytes reserved for the interpreter.
+pub fn max_rom_size(profile: QuirkProfile) -> usize {
+    memory_size(profile) - 0x200
+}
+
+/// A program checked to fit in memory, ready to load into a `Machine`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rom {
+    program: Vec<u8>,
+    profile: QuirkProfile,
+}
+
+impl Rom {
+    /// Reads a ROM file for the platform `profile` targets, compiling it
+    /// first when it is Octo source ending in `.8o`.
+    pub fn load(path: &str, profile: QuirkProfile) -> Result<Rom, StartupError> {
+        let buf = fs::read(path).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => StartupError::FileNotFound(path.to_string()),
+            _ => StartupError::Unreadable {
+                path: path.to_string(),
+                reason: err.to_string(),
+            },
+        })?;
+
+        if Path::new(path).extension().is_some_and(|ext| ext == "8o") {
+            let text = String::from_utf8(buf).map_err(|err| StartupError::Unreadable {
+                path: path.to_string(),
+                reason: err.to_string(),
+            })?;
+            let assembly =
+                assembler::assemble(&text, Path::new(path)).map_err(StartupError::Compile)?;
+            Rom::checked(path, assembly.rom, profile)
+        } else {
+            Rom::checked(path, buf, profile)
+        }
+    }
+
+    /// A program already in memory, such as one bundled with a frontend.
+    pub fn from_bytes(program: Vec<u8>, profile: QuirkProfile) -> Result<Rom, StartupError> {
+        Rom::checked("program", program, profile)
+    }
+
+    /// `name` is only used in errors.
+    fn checked(name: &str, program: Vec<u8>, profile: QuirkProfile) -> Result<Rom, StartupError> {
+        if program.is_empty() {
+            return Err(StartupError::EmptyRom(name.to_string()));
+        }
 
-    let start_of_rom = load_font();
-    let mut rom = [0u8; 4096];
+        let max = max_rom_size(profile);
+        if program.len() > max {
+            return Err(StartupError::RomTooLarge {
+                path: name.to_string(),
+                size: program.len(),
+                max,
+            });
+        }
 
-    for i in 0..start_of_rom.len() {
-        rom[i] = start_of_rom[i];
+        Ok(Rom { program, profile })
     }
 
-    for i in 0..buf.len() {
-        rom[i + 0x200] = buf[i];
+    pub fn program(&self) -> &[u8] {
+        &self.program
     }
 
-    rom
+    /// The platform the ROM was checked to fit.
+    pub fn profile(&self) -> QuirkProfile {
+        self.profile
+    }
+
+    /// The fonts followed by the program at 0x200.
+    pub fn memory(&self) -> [u8; RAM_SIZE] {
+        let start_of_rom = load_font();
+        let mut memory = [0u8; RAM_SIZE];
+
+        memory[..start_of_rom.len()].copy_from_slice(&start_of_rom);
+        memory[0x200..0x200 + self.program.len()].copy_from_slice(&self.program);
+
+        memory
+    }
+
+    /// Identifies the ROM in save states and movies.
+    pub fn hash(&self) -> u64 {
+        savestate::hash(&self.memory())
+    }
 }
 
-fn load_font() -> [u8; 512] {
+/// Loads a ROM into memory after the fonts, compiling it first when it is
+/// Octo source ending in `.8o`.
+#[cfg(test)]
+pub fn load_rom(path: String, profile: QuirkProfile) -> Result<[u8; RAM_SIZE], StartupError> {
+    Ok(Rom::load(&path, profile)?.memory())
+}
+
+pub fn load_font() -> [u8; 512] {
     let mut start_of_rom = [0u8; 0x200];
 
-    for i in 0..FONT.len() {
-        start_of_rom[i + 50] = FONT[i];
-    }
+    start_of_rom[FONT_ADDR..FONT_ADDR + FONT.len()].copy_from_slice(&FONT);
+    start_of_rom[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
 
     start_of_rom
 }
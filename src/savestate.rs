@@ -0,0 +1,193 @@
+use std::fmt;
+
+use crate::{
+    cpu::{CPU, RAM_SIZE},
+    quirks::Quirks,
+    rng::Rng,
+};
+
+const MAGIC: &[u8; 4] = b"R8ST";
+
+/// Bumped whenever the layout changes, states from other versions are
+/// refused rather than misread.
+pub const VERSION: u16 = 3;
+
+pub const SLOTS: u8 = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    NotAState,
+    UnsupportedVersion(u16),
+    WrongRom,
+    Truncated,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a rusteight save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported, expected {}",
+                version, VERSION
+            ),
+            StateError::WrongRom => write!(f, "save state is for a different ROM"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// 64-bit FNV-1a, identifies a ROM by the memory it starts with.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Serializes the whole machine, little-endian after a magic number,
+/// version and the hash of the ROM it is running.
+pub fn save(cpu: &CPU, rom_hash: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(RAM_SIZE + 0x2100);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&rom_hash.to_le_bytes());
+
+    bytes.extend_from_slice(&cpu.regs);
+    bytes.extend_from_slice(&cpu.ram);
+    for row in cpu.display.iter() {
+        bytes.extend_from_slice(row);
+    }
+    bytes.push(cpu.hires as u8);
+    bytes.push(cpu.planes);
+    bytes.extend_from_slice(&cpu.pc.to_le_bytes());
+    bytes.push(cpu.sp as u8);
+    bytes.extend_from_slice(&cpu.index_reg.to_le_bytes());
+    for addr in cpu.stack {
+        bytes.extend_from_slice(&addr.to_le_bytes());
+    }
+    bytes.push(cpu.delay_timer);
+    bytes.push(cpu.sound_timer);
+    bytes.extend_from_slice(&cpu.keypad);
+    match cpu.key_wait {
+        Some(key) => bytes.extend_from_slice(&[1, key]),
+        None => bytes.extend_from_slice(&[0, 0]),
+    }
+    let quirks = cpu.quirks;
+    bytes.extend_from_slice(&[
+        quirks.shift_uses_vy as u8,
+        quirks.load_store_increments_i as u8,
+        quirks.jump_uses_vx as u8,
+        quirks.vf_reset as u8,
+        quirks.clip_sprites as u8,
+        quirks.display_wait as u8,
+    ]);
+    bytes.push(cpu.vblank_wait as u8);
+    bytes.extend_from_slice(&cpu.rpl);
+    bytes.push(cpu.rpl_dirty as u8);
+    bytes.push(cpu.exited as u8);
+    bytes.extend_from_slice(&cpu.audio_pattern);
+    bytes.push(cpu.pitch);
+    bytes.extend_from_slice(&cpu.rng.state.to_le_bytes());
+
+    bytes
+}
+
+/// Reads fields back in the order `save` wrote them.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+}
+
+/// Replaces the machine state with a saved one, keeping debugger settings.
+/// Nothing changes if the state is refused.
+pub fn restore(cpu: &mut CPU, bytes: &[u8], rom_hash: u64) -> Result<(), StateError> {
+    let mut reader = Reader { bytes };
+
+    if reader.take(4).map_err(|_| StateError::NotAState)? != MAGIC {
+        return Err(StateError::NotAState);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    if u64::from_le_bytes(reader.array()?) != rom_hash {
+        return Err(StateError::WrongRom);
+    }
+
+    let mut state = CPU::init_cpu();
+    state.regs = reader.array()?;
+    state.ram.copy_from_slice(reader.take(RAM_SIZE)?);
+    for row in state.display.iter_mut() {
+        *row = reader.array()?;
+    }
+    state.hires = reader.bool()?;
+    state.planes = reader.u8()?;
+    state.pc = reader.u16()?;
+    state.sp = reader.u8()? as usize;
+    state.index_reg = reader.u16()?;
+    for addr in state.stack.iter_mut() {
+        *addr = reader.u16()?;
+    }
+    state.delay_timer = reader.u8()?;
+    state.sound_timer = reader.u8()?;
+    state.keypad = reader.array()?;
+    let [waiting, key] = reader.array()?;
+    state.key_wait = (waiting != 0).then_some(key);
+    state.quirks = Quirks {
+        shift_uses_vy: reader.bool()?,
+        load_store_increments_i: reader.bool()?,
+        jump_uses_vx: reader.bool()?,
+        vf_reset: reader.bool()?,
+        clip_sprites: reader.bool()?,
+        display_wait: reader.bool()?,
+    };
+    state.vblank_wait = reader.bool()?;
+    state.rpl = reader.array()?;
+    state.rpl_dirty = reader.bool()?;
+    state.exited = reader.bool()?;
+    state.audio_pattern = reader.array()?;
+    state.pitch = reader.u8()?;
+    state.rng = Rng::new(u64::from_le_bytes(reader.array()?));
+
+    let bad_key = state
+        .key_wait
+        .is_some_and(|key| key as usize >= state.keypad.len());
+    if state.sp > state.stack.len() || state.planes > 0b11 || bad_key {
+        return Err(StateError::NotAState);
+    }
+
+    // Session and ROM settings rather than machine state
+    state.watching = cpu.watching;
+    state.fault_mode = cpu.fault_mode;
+    state.memory_size = cpu.memory_size;
+    *cpu = state;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use crate::{cpu::CPU, error::CpuFault};
+
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+pub trait Clock {
+    /// Time elapsed since the clock was created.
+    fn elapsed(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Keeps a loop to one pass per 1/60 s frame, for front ends that run the
+/// frames themselves through a `Machine`.
+pub struct Pacer<C: Clock> {
+    clock: C,
+    next_frame: Duration,
+}
+
+impl<C: Clock> Pacer<C> {
+    pub fn new(clock: C) -> Pacer<C> {
+        let next_frame = clock.elapsed() + FRAME_DURATION;
+
+        Pacer { clock, next_frame }
+    }
+
+    pub fn wait_for_next_frame(&mut self) {
+        let now = self.clock.elapsed();
+
+        if now < self.next_frame {
+            self.clock.sleep(self.next_frame - now);
+            self.next_frame += FRAME_DURATION;
+        } else {
+            // Running behind, drop the missed frames rather than racing to catch up
+            self.next_frame = now + FRAME_DURATION;
+        }
+    }
+}
+
+/// Runs the `budget` instructions left in a frame, cut short by a display
+/// wait, then a timer decrement. `stop` is checked before every instruction
+/// and a frame it stops leaves the timers alone, returning `Ok(true)` with
+/// `budget` counted down to what is left for resuming it.
+pub fn run_frame_until<F: FnMut(&CPU) -> bool>(
+    cpu: &mut CPU,
+    budget: &mut u32,
+    mut stop: F,
+) -> Result<bool, CpuFault> {
+    while *budget > 0 {
+        if stop(cpu) {
+            return Ok(true);
+        }
+        *budget -= 1;
+        cpu.tick()?;
+        if cpu.vblank_wait {
+            break;
+        }
+    }
+    cpu.vblank_wait = false;
+    cpu.update_timers();
+    Ok(false)
+}
@@ -0,0 +1,128 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, cpu::CPU, keymap::KeyMap};
+
+/// Directory for files rusteight keeps between runs, following the XDG
+/// base directory layout and falling back to the working directory.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("rusteight");
+    }
+
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".local/share/rusteight"),
+        None => PathBuf::from(".rusteight"),
+    }
+}
+
+/// Directory for user settings such as the key map, following the XDG
+/// base directory layout.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return PathBuf::from(dir).join("rusteight");
+    }
+
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".config/rusteight"),
+        None => PathBuf::from(".rusteight"),
+    }
+}
+
+pub fn keymap_path() -> PathBuf {
+    config_dir().join("keymap.conf")
+}
+
+/// The key map for the ROM being run, from `--keymap` or the default key
+/// map file, falling back to the built-in layouts.
+pub fn load_keymap(config: &Config) -> KeyMap {
+    let rom_name = Path::new(&config.rom_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = match &config.keymap_path {
+        Some(path) => PathBuf::from(path),
+        None => keymap_path(),
+    };
+
+    // A missing default key map file just means the built-in layouts are used
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            if config.keymap_path.is_some() {
+                eprintln!("could not read {}: {}", path.display(), err);
+            }
+            String::new()
+        }
+    };
+
+    match KeyMap::from_config(&text, &rom_name, config.layout.as_deref()) {
+        Ok(keymap) => keymap,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            KeyMap::default()
+        }
+    }
+}
+
+pub fn flags_path(rom_path: &str) -> PathBuf {
+    let rom_name = Path::new(rom_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    data_dir().join("flags").join(format!("{}.flags", rom_name))
+}
+
+/// Save state files are named by ROM hash, so renaming a ROM keeps its
+/// states and two ROMs with the same name do not share them.
+pub fn state_path(rom_hash: u64, slot: u8) -> PathBuf {
+    data_dir()
+        .join("states")
+        .join(format!("{:016x}.{}.state", rom_hash, slot))
+}
+
+/// Reads the SUPER-CHIP RPL user flags, a missing file means no flags have
+/// been saved yet.
+pub fn load_flags(path: &Path) -> io::Result<[u8; 16]> {
+    let mut flags = [0u8; 16];
+
+    match fs::read(path) {
+        Ok(bytes) => {
+            let len = bytes.len().min(flags.len());
+            flags[..len].copy_from_slice(&bytes[..len]);
+            Ok(flags)
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(flags),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_flags(path: &Path, flags: &[u8; 16]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, flags)
+}
+
+pub fn save_state(path: &Path, state: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, state)
+}
+
+/// Writes the SUPER-CHIP flag registers out if the ROM changed them. A
+/// failed write is not retried.
+pub fn flush_flags(cpu: &mut CPU, flags_path: &Path) -> io::Result<()> {
+    if !cpu.rpl_dirty {
+        return Ok(());
+    }
+    cpu.rpl_dirty = false;
+    save_flags(flags_path, &cpu.rpl)
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Stdout, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use crate::{
+    audio::AudioSink,
+    cells::{self, CellStyle},
+    config::{Config, Palette},
+    cpu::CPU,
+    error::StartupError,
+    frontend::{self, Frontend},
+    keymap::KeyMap,
+    keypad::{InputFrame, InputSource, KeyStroke},
+    machine::Machine,
+    movie::Playback,
+    scheduler::SystemClock,
+    storage,
+    trace::Tracer,
+    video::VideoSink,
+};
+
+/// Presents between full redraws. Anything else printed over the screen is
+/// gone by the next one.
+const REDRAW_INTERVAL: u64 = 30;
+
+/// Lines of the latest messages kept in the panel.
+const MESSAGE_LINES: usize = 4;
+
+/// Polls a key counts as held for after a press, when the terminal only
+/// reports presses. Holding a key down repeats the press, keeping it held
+/// once the keyboard's repeat delay has passed.
+const HOLD_POLLS: u64 = 10;
+
+/// Rings the terminal bell as the buzzer starts, the closest a remote
+/// terminal has to a tone.
+pub struct TerminalBell {
+    playing: bool,
+    muted: bool,
+}
+
+impl TerminalBell {
+    pub fn new(muted: bool) -> TerminalBell {
+        TerminalBell {
+            playing: false,
+            muted,
+        }
+    }
+}
+
+impl AudioSink for TerminalBell {
+    fn update(&mut self, playing: bool) {
+        if playing && !self.playing && !self.muted {
+            let mut out = io::stdout();
+            let _ = out.write_all(b"\x07").and_then(|_| out.flush());
+        }
+        self.playing = playing;
+    }
+
+    fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+}
+
+/// Draws on the terminal's alternate screen, which is left on drop.
+pub struct TerminalVideo {
+    out: Stdout,
+    style: CellStyle,
+    palette: Palette,
+    last: Vec<String>,
+    presents: u64,
+    messages: VecDeque<String>,
+    /// Messages since the last present, printed on leaving the screen in
+    /// case they were never drawn.
+    unshown: Vec<String>,
+}
+
+impl TerminalVideo {
+    pub fn new(style: CellStyle, palette: Palette) -> io::Result<TerminalVideo> {
+        let mut out = io::stdout();
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        Ok(TerminalVideo {
+            out,
+            style,
+            palette,
+            last: Vec::new(),
+            presents: 0,
+            messages: VecDeque::new(),
+            unshown: Vec::new(),
+        })
+    }
+
+    fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        for (row, line) in lines.iter().enumerate() {
+            queue!(
+                self.out,
+                MoveTo(0, row as u16),
+                Print(line),
+                Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        queue!(self.out, Clear(ClearType::FromCursorDown))?;
+        self.out.flush()
+    }
+}
+
+impl VideoSink for TerminalVideo {
+    fn present(&mut self, cpu: &CPU, status: Option<&str>) {
+        let messages: Vec<String> = self.messages.iter().cloned().collect();
+        let lines = cells::screen(cpu, status, &messages, self.style, &self.palette);
+        self.presents += 1;
+        self.unshown.clear();
+
+        // Over SSH every byte counts, so unchanged frames are skipped
+        if lines != self.last || self.presents.is_multiple_of(REDRAW_INTERVAL) {
+            let _ = self.draw(&lines);
+            self.last = lines;
+        }
+    }
+
+    fn message(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.messages.len() == MESSAGE_LINES {
+                self.messages.pop_front();
+            }
+            self.messages.push_back(line.to_string());
+        }
+        self.unshown.push(text.to_string());
+    }
+}
+
+impl Drop for TerminalVideo {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, Show, LeaveAlternateScreen);
+        for text in &self.unshown {
+            println!("{}", text);
+        }
+    }
+}
+
+/// Punctuation key names the key map shares with the SDL window.
+const PUNCTUATION: &str = "'\"&,./;-=[]\\`";
+
+/// Letters typed on keys with no name of their own, by the name of the key
+/// in the same place on a US keyboard, as the SDL window reports them.
+const PLACED_LETTERS: [(char, &str); 10] = [
+    // AZERTY
+    ('é', "2"),
+    ('è', "7"),
+    ('ç', "9"),
+    ('à', "0"),
+    ('ù', "'"),
+    ('²', "`"),
+    // QWERTZ
+    ('ü', "["),
+    ('ö', ";"),
+    ('ä', "'"),
+    ('ß', "-"),
+];
+
+/// Named keys a terminal reports, in `key_name` spelling.
+const NAMED_KEYS: [&str; 6] = ["up", "down", "left", "right", "return", "tab"];
+
+fn is_char_name(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || PUNCTUATION.contains(c)
+}
+
+/// The key map name of a key the terminal reported, the same one the SDL
+/// window gives it.
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Up => "up",
+        KeyCode::Down => "down",
+        KeyCode::Left => "left",
+        KeyCode::Right => "right",
+        KeyCode::Enter => "return",
+        KeyCode::Tab => "tab",
+        KeyCode::Char(c) => {
+            let c = c.to_lowercase().next()?;
+            if is_char_name(c) {
+                return Some(c.to_string());
+            }
+            PLACED_LETTERS
+                .iter()
+                .find(|&&(letter, _)| letter == c)
+                .map(|&(_, name)| name)?
+        }
+        _ => return None,
+    };
+
+    Some(name.to_string())
+}
+
+/// Fails naming the keys `keymap` binds that a terminal never reports, such
+/// as Shift or the keypad, which would leave their hex keys unreachable.
+pub fn check_keymap(keymap: &KeyMap) -> Result<(), StartupError> {
+    let unreadable: Vec<String> = keymap
+        .key_names()
+        .into_iter()
+        .filter(|name| {
+            let mut chars = name.chars();
+            let char_name =
+                matches!((chars.next(), chars.next()), (Some(c), None) if is_char_name(c));
+            !char_name && !NAMED_KEYS.contains(name)
+        })
+        .map(str::to_string)
+        .collect();
+
+    if unreadable.is_empty() {
+        Ok(())
+    } else {
+        Err(StartupError::TerminalKeys(unreadable))
+    }
+}
+
+/// Which hex keys are held, from terminal key events. Most terminals only
+/// report presses, so a key is let go `HOLD_POLLS` polls after its last
+/// one. Terminals that report releases let go of it on release.
+pub struct TerminalKeys {
+    keymap: KeyMap,
+    releases: bool,
+    polls: u64,
+    held_until: [u64; 16],
+    rewind_until: u64,
+}
+
+impl TerminalKeys {
+    pub fn new(keymap: KeyMap, releases: bool) -> TerminalKeys {
+        TerminalKeys {
+            keymap,
+            releases,
+            polls: 0,
+            held_until: [0; 16],
+            rewind_until: 0,
+        }
+    }
+
+    /// Updates the held keys, returning the emulator hotkey the event
+    /// triggered, if any.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<KeyStroke> {
+        let pressed = key.kind == KeyEventKind::Press;
+        let until = match key.kind {
+            KeyEventKind::Release => self.polls,
+            _ if self.releases => u64::MAX,
+            _ => self.polls + HOLD_POLLS,
+        };
+
+        let name = match key.code {
+            KeyCode::Esc if pressed => return Some(KeyStroke::Quit),
+            KeyCode::Char('c') if pressed && key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(KeyStroke::Quit)
+            }
+            KeyCode::Char(' ') | KeyCode::F(6) if pressed => return Some(KeyStroke::Next),
+            KeyCode::F(1) if pressed => return Some(KeyStroke::Mute),
+            KeyCode::F(5) if pressed => return Some(KeyStroke::Pause),
+            KeyCode::F(7) if pressed => return Some(KeyStroke::StepOver),
+            KeyCode::F(8) if pressed => return Some(KeyStroke::StepOut),
+            KeyCode::F(9) if pressed => return Some(KeyStroke::SaveState),
+            KeyCode::F(10) if pressed => return Some(KeyStroke::LoadState),
+            KeyCode::F(11) if pressed => return Some(KeyStroke::NextSlot),
+            KeyCode::Backspace => {
+                self.rewind_until = until;
+                return None;
+            }
+            code => key_name(code)?,
+        };
+
+        if let Some(key) = self.keymap.hex_key(&name) {
+            self.held_until[key] = until;
+        }
+        None
+    }
+
+    /// Ends a poll, returning the keys held for the coming frame.
+    pub fn frame(&mut self, hotkey: Option<KeyStroke>) -> InputFrame {
+        let frame = InputFrame {
+            keys: self.held_until.map(|until| (self.polls < until) as u8),
+            hotkey,
+            rewinding: self.polls < self.rewind_until,
+        };
+        self.polls += 1;
+
+        frame
+    }
+}
+
+/// Reads the keyboard with the terminal in raw mode, restored on drop.
+pub struct TerminalInput {
+    keys: TerminalKeys,
+}
+
+impl TerminalInput {
+    pub fn new(keymap: KeyMap) -> io::Result<TerminalInput> {
+        terminal::enable_raw_mode()?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(TerminalInput {
+            keys: TerminalKeys::new(keymap, releases),
+        })
+    }
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, _frame: u64) -> InputFrame {
+        let mut hotkey = None;
+
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                let stroke = self.keys.handle_key(key);
+                if hotkey.is_none() {
+                    hotkey = stroke;
+                }
+            }
+        }
+
+        self.keys.frame(hotkey)
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        if self.keys.releases {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs the ROM in the terminal, for machines with no display such as
+/// over SSH.
+pub fn run(
+    machine: &mut Machine,
+    config: &Config,
+    flags_path: &Path,
+    tracer: Option<Tracer>,
+    playback: Option<Playback>,
+    style: CellStyle,
+) -> Result<(), StartupError> {
+    let unavailable = |err: io::Error| StartupError::TerminalInit(err.to_string());
+    // Read before the screen is taken over, as it warns on stderr
+    let keymap = storage::load_keymap(config);
+    check_keymap(&keymap)?;
+    // Declared first so raw mode is off by the time the video prints
+    let mut video = TerminalVideo::new(style, config.palette).map_err(unavailable)?;
+    let mut input = TerminalInput::new(keymap).map_err(unavailable)?;
+    let mut audio = TerminalBell::new(config.audio.muted);
+
+    frontend::run(
+        machine,
+        SystemClock::new(),
+        config,
+        flags_path,
+        tracer,
+        playback,
+        Frontend {
+            video: &mut video,
+            input: &mut input,
+            audio: &mut audio,
+        },
+    );
+
+    Ok(())
+}
@@ -1,28 +1,66 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use crate::cpu::CPU;
-    use crate::keypad::{check_for_key_press, KeyStroke};
-    use crate::window_manager::WindowManager;
-    use crate::{cpu, rom_loader};
+    use crate::assembler::{assemble, AssembleError};
+    use crate::audio::{
+        AudioSettings, AudioSink, NullAudio, RecordingAudio, ToneGenerator, Waveform, SAMPLE_RATE,
+    };
+    use crate::cells::{self, CellStyle};
+    use crate::config::{Command, Config, Palette};
+    use crate::cpu::{CPU, DISPLAY_HEIGHT, DISPLAY_WIDTH, RAM_SIZE};
+    use crate::debugger::{Breakpoint, Compare, DebugCommand, Debugger, RegCondition};
+    use crate::disassembler::{disassemble, InstructionSet};
+    use crate::error::{CpuFault, FaultKind, FaultMode, StartupError};
+    use crate::frontend::{self, Frontend};
+    use crate::headless::{self, InputScript};
+    use crate::instruction::Instruction;
+    use crate::keymap::{KeyMap, LAYOUTS};
+    use crate::keypad::{InputFrame, InputSource, KeyStroke, QueuedInput};
+    use crate::machine::Machine;
+    use crate::movie::{self, Movie, Playback};
+    use crate::quirks::{QuirkProfile, Quirks};
+    use crate::rewind::{self, Rewind};
+    use crate::rng::Rng;
+    use crate::rom_loader::Rom;
+    use crate::savestate::{self, StateError};
+    use crate::scheduler::{Clock, Pacer, FRAME_DURATION};
+    #[cfg(feature = "terminal")]
+    use crate::terminal::{self, TerminalKeys};
+    use crate::trace::{TraceSettings, Tracer};
+    use crate::video::MemoryVideo;
+    #[cfg(feature = "sdl")]
+    use crate::windowed::KeypadState;
+    use crate::{cpu, rom_loader, storage};
+    #[cfg(feature = "terminal")]
+    use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
+    #[cfg(feature = "sdl")]
+    use sdl2::event::Event;
+    #[cfg(feature = "sdl")]
+    use sdl2::keyboard::{Keycode, Mod, Scancode};
+    use std::cell::{Cell, RefCell};
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Duration;
 
     #[test]
     fn init_cpu() {
         let cpu = CPU::init_cpu();
 
         assert_eq!(cpu.regs, [0u8; 16]);
-        assert_eq!(cpu.ram, [0u8; 4096]);
-        assert_eq!(cpu.display, [[0u8; 64]; 32]);
+        assert_eq!(cpu.ram, [0u8; RAM_SIZE]);
+        assert_eq!(cpu.display, [[0u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT]);
+        assert!(!cpu.hires);
         assert_eq!(cpu.pc, 0x200);
         assert_eq!(cpu.sp, 0);
         assert_eq!(cpu.stack, [0; 16]);
-        assert_eq!(cpu.delay_timer, 60);
-        assert_eq!(cpu.sound_timer, 60);
+        assert_eq!(cpu.delay_timer, 0);
+        assert_eq!(cpu.sound_timer, 0);
     }
 
     #[test]
     fn load_rom() {
         let path = String::from("./src/ROMS/IBM.ch8");
-        let rom = rom_loader::load_rom(path);
+        let rom = rom_loader::load_rom(path, QuirkProfile::CosmacVip).unwrap();
 
         let font = rom_loader::FONT;
 
@@ -47,21 +85,54 @@ mod tests {
 
     #[test]
     fn execute() {
-        let inst = 0x1228 as u16;
+        let mut cpu = cpu::init_test_cpu();
+
+        assert_eq!(cpu.tick(), Ok(Instruction::Cls));
+        assert_eq!(cpu.pc, 0x202);
+    }
 
-        let opcode = ((inst & 0xF000) >> 12) as u8;
-        let x = ((inst & 0x0F00) >> 8) as usize;
-        let y = ((inst & 0x00F0) >> 4) as usize;
-        let n = (inst & 0x000F) as u8;
-        let nn = (inst & 0x00FF) as u8;
-        let nnn = inst & 0x0FFF;
+    #[test]
+    fn decode() {
+        assert_eq!(
+            Instruction::decode(0x1228),
+            Instruction::Jmp { addr: 0x228 }
+        );
+        assert_eq!(
+            Instruction::decode(0x8AB4),
+            Instruction::AddRegReg { x: 0xA, y: 0xB }
+        );
+        assert_eq!(
+            Instruction::decode(0xD015),
+            Instruction::Draw { x: 0, y: 1, n: 5 }
+        );
+        assert_eq!(
+            Instruction::decode(0x00C3),
+            Instruction::ScrollDown { n: 3 }
+        );
+        assert_eq!(Instruction::decode(0xF000), Instruction::LdLongIndex);
+        assert_eq!(Instruction::decode(0xF100), Instruction::Invalid(0xF100));
+        assert_eq!(Instruction::decode(0x8008), Instruction::Invalid(0x8008));
+        assert_eq!(Instruction::decode(0x9AB1), Instruction::Invalid(0x9AB1));
+        assert_eq!(Instruction::decode(0xE19F), Instruction::Invalid(0xE19F));
+    }
 
-        assert_eq!(opcode, 0x1);
-        assert_eq!(x, 0x2);
-        assert_eq!(y, 0x2);
-        assert_eq!(n, 0x8);
-        assert_eq!(nn, 0x28);
-        assert_eq!(nnn, 0x228);
+    #[test]
+    fn instruction_mnemonics() {
+        let mnemonics = [
+            (0x00E0, "CLS"),
+            (0x1228, "JP 0x228"),
+            (0x3A2F, "SE VA, 0x2F"),
+            (0x8AB4, "ADD VA, VB"),
+            (0xD015, "DRW V0, V1, 5"),
+            (0xF265, "LD V2, [I]"),
+            (0xF033, "LD B, V0"),
+            (0x5232, "SAVE V2, V3"),
+            (0x0123, "DW 0x0123"),
+        ];
+
+        for (inst, mnemonic) in mnemonics {
+            assert_eq!(Instruction::decode(inst).to_string(), mnemonic);
+        }
     }
 
     #[test]
@@ -70,7 +141,7 @@ mod tests {
 
         cpu.display[0][0] = 0xFF;
 
-        cpu.display = [[0u8; 64]; 32];
+        cpu.display = [[0u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
 
         assert_eq!(cpu.display[0][0], 0);
     }
@@ -78,7 +149,7 @@ mod tests {
     #[test]
     fn jmp_to_addr() {
         let mut cpu = cpu::init_test_cpu();
-        let addr = 0xFFAE as u16;
+        let addr: u16 = 0xFFAE;
         cpu.pc = addr;
 
         assert_eq!(cpu.pc, 0xFFAE);
@@ -92,6 +163,8 @@ mod tests {
         let nn = 0xFA;
 
         cpu.regs[x] = nn;
+
+        assert_eq!(cpu.regs[x], 0xFA);
     }
 
     #[test]
@@ -121,32 +194,26 @@ mod tests {
 
     #[test]
     fn ret() {
-        let mut cpu = cpu::init_test_cpu();
-
-        cpu.sp = 10;
-        cpu.stack[cpu.sp] = 0xAAAA;
-
-        cpu.pc = cpu.stack[cpu.sp as usize] as u16;
-        if cpu.sp > 0 {
-            cpu.sp -= 1;
-        }
-        assert_eq!(cpu.pc, 0xAAAA);
-        assert_eq!(cpu.sp, 9);
+        // Call 0x206, which returns straight away
+        let mut cpu = quirks_cpu(
+            QuirkProfile::CosmacVip,
+            &[0x22, 0x06, 0, 0, 0, 0, 0x00, 0xEE],
+        );
+
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.sp, 0);
     }
 
     #[test]
     fn call_addr() {
-        let mut cpu = cpu::init_test_cpu();
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0x2A, 0xBB]);
 
-        let addr = 0xFABB;
-
-        cpu.sp += 1;
-        cpu.stack[cpu.sp] = cpu.pc;
-        cpu.pc = addr;
-
-        assert_eq!(cpu.stack[1], 0x200);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.stack[0], 0x202);
         assert_eq!(cpu.sp, 1);
-        assert_eq!(cpu.pc, 0xFABB);
+        assert_eq!(cpu.pc, 0xABB);
     }
 
     #[test]
@@ -481,30 +548,27 @@ mod tests {
 
     #[test]
     fn ld_key() {
-        let mut cpu = cpu::init_test_cpu();
-        let mut window = WindowManager::init_sdl();
+        // Wait for a key in V5
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0xF5, 0x0A]);
+        let mut keys = [0u8; 16];
 
-        let x = 5;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x200);
 
-        'running: loop {
-            let keypad = check_for_key_press(&mut window.event_pump, &mut cpu);
-            cpu.set_key(&keypad);
+        keys[0x6] = 1;
+        cpu.set_key(&KeyStroke::Key(keys));
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        // Still waiting while the key is held
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.key_wait, Some(0x6));
 
-            if cpu.keypad == [0u8; 16] {
-                cpu.pc -= 2;
-                println!("pc - 2")
-            } else {
-                println!("reg {}", cpu.regs[x]);
-                for i in 0..cpu.keypad.len() {
-                    if cpu.keypad[i] == 1 {
-                        cpu.regs[x] = i as u8;
-                        println!("reg {}", cpu.regs[x]);
-
-                        break 'running;
-                    }
-                }
-            }
-        }
+        keys[0x6] = 0;
+        cpu.set_key(&KeyStroke::Key(keys));
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.regs[5], 0x6);
+        assert_eq!(cpu.key_wait, None);
     }
 
     #[test]
@@ -561,7 +625,7 @@ mod tests {
         let mut cpu = cpu::init_test_cpu();
 
         let x = 5;
-        cpu.regs[x] = 012;
+        cpu.regs[x] = 12;
 
         cpu.ram[cpu.index_reg as usize] = cpu.regs[x] / 100;
         cpu.ram[cpu.index_reg as usize + 1] = (cpu.regs[x] % 100) / 10;
@@ -570,7 +634,7 @@ mod tests {
         assert_eq!(cpu.ram[cpu.index_reg as usize], 0);
         assert_eq!(cpu.ram[cpu.index_reg as usize + 1], 1);
         assert_eq!(cpu.ram[cpu.index_reg as usize + 2], 2);
-    }   
+    }
 
     #[test]
     fn ld_reg_to_ram() {
@@ -584,12 +648,11 @@ mod tests {
         cpu.regs[4] = 5;
         cpu.regs[5] = 6;
 
-
         if x == 0 {
             cpu.ram[cpu.index_reg as usize] = cpu.regs[0];
         }
 
-        for i in 0..x + 1{
+        for i in 0..x + 1 {
             cpu.ram[cpu.index_reg as usize + i] = cpu.regs[i];
         }
 
@@ -599,9 +662,8 @@ mod tests {
         assert_eq!(cpu.ram[3], 4);
         assert_eq!(cpu.ram[4], 5);
         assert_eq!(cpu.ram[5], 6);
-
     }
-    
+
     #[test]
     fn ld_ram_to_reg() {
         let mut cpu = cpu::init_test_cpu();
@@ -615,7 +677,6 @@ mod tests {
         cpu.ram[4] = 5;
         cpu.ram[5] = 6;
 
-
         if x == 0 {
             cpu.regs[0] = cpu.ram[cpu.index_reg as usize];
         }
@@ -630,7 +691,2391 @@ mod tests {
         assert_eq!(cpu.regs[3], 4);
         assert_eq!(cpu.regs[4], 5);
         assert_eq!(cpu.regs[5], 6);
+    }
+
+    fn args(args: &[&str]) -> Result<Command, String> {
+        Config::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_args() {
+        let command = args(&[
+            "-i", "20", "--scale", "5", "-p", "amber", "-q", "schip", "game.ch8",
+        ]);
+
+        let mut expected = Config::new("game.ch8");
+        expected.instructions_per_frame = 20;
+        expected.scale = 5;
+        expected.palette = Palette::from_name("amber").unwrap();
+        expected.quirks = QuirkProfile::Schip;
+
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert_eq!(args(&["game.ch8", "--help"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn parse_args_errors() {
+        assert!(args(&[]).is_err());
+        assert!(args(&["--ipf", "fast", "game.ch8"]).is_err());
+        assert!(args(&["--scale"]).is_err());
+        assert!(args(&["--quirks", "nes", "game.ch8"]).is_err());
+        assert!(args(&["--turbo", "game.ch8"]).is_err());
+        assert!(args(&["a.ch8", "b.ch8"]).is_err());
+        assert_eq!(
+            args(&["--record", "a.r8m", "--play", "b.r8m", "a.ch8"]),
+            Err(String::from("--record and --play cannot be used together"))
+        );
+        assert!(args(&["--record", "a.r8m", "--headless", "a.ch8"]).is_err());
+    }
+
+    #[test]
+    fn palette() {
+        let palette = Palette::from_name("102030,#FFA500").unwrap();
+
+        assert_eq!(palette.background, (0x10, 0x20, 0x30));
+        assert_eq!(palette.foreground, (0xFF, 0xA5, 0x00));
+        assert_eq!(palette.colour(3), (0xFF, 0xA5, 0x00));
+
+        let palette = Palette::from_name("000000,FFFFFF,FF0000,00FF00").unwrap();
+
+        assert_eq!(palette.colour(0), (0, 0, 0));
+        assert_eq!(palette.colour(1), (0xFF, 0xFF, 0xFF));
+        assert_eq!(palette.colour(2), (0xFF, 0, 0));
+        assert_eq!(palette.colour(3), (0, 0xFF, 0));
+        assert_eq!(Palette::from_name("000000,FFFFFF,FF0000"), None);
+        assert_eq!(Palette::from_name("10203,FFA500"), None);
+        assert_eq!(Palette::from_name("purple"), None);
+    }
+
+    struct ManualClock {
+        now: Rc<Cell<Duration>>,
+    }
+
+    impl Clock for ManualClock {
+        fn elapsed(&self) -> Duration {
+            self.now.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+        }
+    }
+
+    /// A machine running `program` with the quirks of `profile`, 10
+    /// instructions a frame.
+    fn quirks_machine(profile: QuirkProfile, program: &[u8]) -> Machine {
+        let rom = Rom::from_bytes(program.to_vec(), profile).unwrap();
+        Machine::new(&rom, Quirks::preset(profile))
+    }
+
+    #[test]
+    fn machine_runs_instructions_per_frame() {
+        let program: Vec<u8> = [0x70, 0x01].repeat(100);
+        let mut machine = quirks_machine(QuirkProfile::Chip48, &program);
+        machine.set_instructions_per_frame(12);
+
+        machine.run_frame().unwrap();
+
+        assert_eq!(machine.cpu().regs[0], 12);
+        assert_eq!(machine.cpu().pc, 0x200 + 24);
+        assert_eq!(machine.frame_count(), 1);
+    }
+
+    #[test]
+    fn machine_decrements_timers_once_per_frame() {
+        // Loop on a jump to self so the instructions do not touch the timers
+        let mut machine = quirks_machine(QuirkProfile::Chip48, &[0x12, 0x00]);
+        machine.set_instructions_per_frame(50);
+        machine.cpu_mut().delay_timer = 3;
+        machine.cpu_mut().sound_timer = 1;
+
+        machine.run_frame().unwrap();
+
+        assert_eq!(machine.cpu().delay_timer, 2);
+        assert_eq!(machine.cpu().sound_timer, 0);
+
+        for _ in 0..5 {
+            machine.run_frame().unwrap();
+        }
+
+        assert_eq!(machine.cpu().delay_timer, 0);
+        assert_eq!(machine.cpu().sound_timer, 0);
+    }
+
+    #[test]
+    fn pacer_holds_frame_rate() {
+        let now = Rc::new(Cell::new(Duration::ZERO));
+        let mut pacer = Pacer::new(ManualClock { now: now.clone() });
+
+        now.set(Duration::from_millis(4));
+        pacer.wait_for_next_frame();
+        assert_eq!(now.get(), FRAME_DURATION);
+
+        pacer.wait_for_next_frame();
+        assert_eq!(now.get(), FRAME_DURATION * 2);
+
+        // A frame that overruns is not made up for by skipping the next sleep
+        now.set(FRAME_DURATION * 10);
+        pacer.wait_for_next_frame();
+        assert_eq!(now.get(), FRAME_DURATION * 10);
+
+        pacer.wait_for_next_frame();
+        assert_eq!(now.get(), FRAME_DURATION * 11);
+    }
+
+    fn quirks_cpu(profile: QuirkProfile, program: &[u8]) -> CPU {
+        let mut cpu = CPU::init_cpu();
+        cpu.quirks = Quirks::preset(profile);
+        cpu.memory_size = rom_loader::memory_size(profile);
+        cpu.ram[..0x200].copy_from_slice(&rom_loader::load_font());
+        cpu.ram[0x200..0x200 + program.len()].copy_from_slice(program);
+
+        cpu
+    }
+
+    #[test]
+    fn quirk_shift_uses_vy() {
+        // V1 = 0b101, V2 = 0b110, V1 >>= 1
+        let program = [0x61, 0x05, 0x62, 0x06, 0x81, 0x26];
+
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.regs[1], 0b11);
+        assert_eq!(cpu.regs[0xF], 0);
+
+        let mut cpu = quirks_cpu(QuirkProfile::Schip, &program);
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.regs[1], 0b10);
+        assert_eq!(cpu.regs[0xF], 1);
+    }
+
+    #[test]
+    fn quirk_load_store_increments_i() {
+        // I = 0x300, store V0..V2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.index_reg, 0x303);
+
+        let mut cpu = quirks_cpu(QuirkProfile::Chip48, &program);
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.index_reg, 0x300);
+    }
+
+    #[test]
+    fn quirk_jump_uses_vx() {
+        // V0 = 1, V3 = 3, jump to 0x310 + reg
+        let program = [0x60, 0x01, 0x63, 0x03, 0xB3, 0x10];
+
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.pc, 0x311);
+
+        let mut cpu = quirks_cpu(QuirkProfile::Schip, &program);
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.pc, 0x313);
+    }
+
+    #[test]
+    fn quirk_vf_reset() {
+        // VF = 1, V0 |= V1
+        let program = [0x6F, 0x01, 0x80, 0x11];
+
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.regs[0xF], 0);
+
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &program);
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.regs[0xF], 1);
+    }
+
+    #[test]
+    fn quirk_clip_sprites() {
+        // V0 = 62, V1 = 31, I = font 0, draw 5 rows
+        let program = [0x60, 0x3E, 0x61, 0x1F, 0xA0, 0x32, 0xD0, 0x15];
+
+        for profile in [
+            QuirkProfile::CosmacVip,
+            QuirkProfile::Chip48,
+            QuirkProfile::Schip,
+        ] {
+            let mut cpu = quirks_cpu(profile, &program);
+            for _ in 0..4 {
+                cpu.tick().unwrap();
+            }
+            assert_eq!(cpu.display[31][62..64], [1, 1]);
+            assert_eq!(cpu.display[31][0..2], [0, 0]);
+            assert_eq!(cpu.display[0][62..64], [0, 0]);
+        }
+
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &program);
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.display[31][62..64], [1, 1]);
+        assert_eq!(cpu.display[31][0..2], [1, 1]);
+        assert_eq!(cpu.display[0][62..64], [1, 0]);
+    }
+
+    #[test]
+    fn quirk_display_wait() {
+        // Draw, then V0 = 1 and loop
+        let program = [0xD0, 0x01, 0x60, 0x01, 0x12, 0x04];
+
+        let mut machine = quirks_machine(QuirkProfile::CosmacVip, &program);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.cpu().pc, 0x202);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.cpu().regs[0], 1);
+
+        let mut cpu = quirks_cpu(QuirkProfile::Schip, &program);
+        cpu.tick().unwrap();
+        assert!(!cpu.vblank_wait);
+    }
+
+    #[test]
+    fn hires() {
+        // hires, lores, hires
+        let mut cpu = quirks_cpu(QuirkProfile::Schip, &[0x00, 0xFF, 0x00, 0xFE, 0x00, 0xFF]);
+
+        cpu.tick().unwrap();
+        assert!(cpu.hires);
+        assert_eq!(cpu.screen_size(), (128, 64));
+
+        cpu.display[10][10] = 1;
+        cpu.tick().unwrap();
+        assert!(!cpu.hires);
+        assert_eq!(cpu.screen_size(), (64, 32));
+        assert_eq!(cpu.display[10][10], 0);
+
+        cpu.tick().unwrap();
+        assert!(cpu.hires);
+    }
+
+    #[test]
+    fn hires_draw_large_sprite() {
+        // hires, V0 = 120, V1 = 60, draw 16x16 sprite at I = 0x300
+        let mut cpu = quirks_cpu(
+            QuirkProfile::Schip,
+            &[0x00, 0xFF, 0x60, 0x78, 0x61, 0x3C, 0xA3, 0x00, 0xD0, 0x10],
+        );
+        cpu.ram[0x300..0x320].fill(0xFF);
+
+        for _ in 0..5 {
+            cpu.tick().unwrap();
+        }
+
+        assert_eq!(cpu.display[60][120..128], [1; 8]);
+        assert_eq!(cpu.display[63][120..128], [1; 8]);
+        assert_eq!(cpu.display[60][0], 0);
+        assert_eq!(cpu.display[0][120], 0);
+        assert_eq!(cpu.regs[0xF], 0);
+
+        cpu.pc = 0x208;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display[60][120], 0);
+        assert_eq!(cpu.regs[0xF], 1);
+    }
+
+    #[test]
+    fn scroll() {
+        // scroll down 3, scroll right, scroll left
+        let mut cpu = quirks_cpu(QuirkProfile::Schip, &[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC]);
+        cpu.display[0][0] = 1;
+        cpu.display[31][0] = 1;
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display[0][0], 0);
+        assert_eq!(cpu.display[3][0], 1);
+        // Low resolution scrolling stays inside the 64x32 area
+        assert_eq!(cpu.display[34][0], 0);
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display[3][0], 0);
+        assert_eq!(cpu.display[3][4], 1);
+
+        cpu.display[3][62] = 1;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display[3][0], 1);
+        assert_eq!(cpu.display[3][58], 1);
+        assert_eq!(cpu.display[3][62], 0);
+    }
+
+    #[test]
+    fn big_font() {
+        // V0 = 7, I = big font 7, V1 = 2, I = font 2
+        let mut cpu = quirks_cpu(
+            QuirkProfile::Schip,
+            &[0x60, 0x07, 0xF0, 0x30, 0x61, 0x02, 0xF1, 0x29],
+        );
+
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        let addr = cpu.index_reg as usize;
+        assert_eq!(cpu.ram[addr..addr + 10], rom_loader::BIG_FONT[70..80]);
+
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        let addr = cpu.index_reg as usize;
+        assert_eq!(cpu.ram[addr..addr + 5], rom_loader::FONT[10..15]);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // V0 = 1, V1 = 2, store V0..V1, V0 = 0, V1 = 0, load V0..V1
+        let mut cpu = quirks_cpu(
+            QuirkProfile::Schip,
+            &[
+                0x60, 0x01, 0x61, 0x02, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85,
+            ],
+        );
+
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.rpl[..3], [1, 2, 0]);
+        assert!(cpu.rpl_dirty);
+
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.regs[..2], [1, 2]);
+    }
+
+    #[test]
+    fn rpl_flags_persist() {
+        let path = std::env::temp_dir()
+            .join(format!("rusteight-{}", std::process::id()))
+            .join("test.flags");
+        let mut flags = [0u8; 16];
+        flags[0] = 42;
+        flags[15] = 7;
+
+        assert_eq!(storage::load_flags(&path).unwrap(), [0u8; 16]);
+        storage::save_flags(&path, &flags).unwrap();
+        assert_eq!(storage::load_flags(&path).unwrap(), flags);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn exit() {
+        let mut cpu = quirks_cpu(QuirkProfile::Schip, &[0x00, 0xFD, 0x60, 0x01]);
+
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+
+        assert!(cpu.exited);
+        assert_eq!(cpu.regs[0], 0);
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn xo_long_index_load() {
+        // I = 0xABCD, V0 = 1, skip next if V0 == 1 over a long load
+        let mut cpu = quirks_cpu(
+            QuirkProfile::XoChip,
+            &[
+                0xF0, 0x00, 0xAB, 0xCD, 0x60, 0x01, 0x30, 0x01, 0xF0, 0x00, 0x12, 0x34,
+            ],
+        );
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.index_reg, 0xABCD);
+        assert_eq!(cpu.pc, 0x204);
+
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x20C);
+    }
+
+    #[test]
+    fn xo_ram_is_64k() {
+        // I = 0xFFF0, store V0..V2
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &[0xF0, 0x00, 0xFF, 0xF0, 0xF2, 0x55]);
+        cpu.regs[..3].copy_from_slice(&[7, 8, 9]);
+
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+
+        assert_eq!(cpu.ram[0xFFF0..0xFFF3], [7, 8, 9]);
+        assert_eq!(cpu.index_reg, 0xFFF3);
+    }
+
+    #[test]
+    fn xo_register_range() {
+        // I = 0x300, save V2..V4, save V4..V2 at 0x310, load V7..V5 from 0x300
+        let mut cpu = quirks_cpu(
+            QuirkProfile::XoChip,
+            &[
+                0xA3, 0x00, 0x52, 0x42, 0xA3, 0x10, 0x54, 0x22, 0xA3, 0x00, 0x57, 0x53,
+            ],
+        );
+        cpu.regs[2..5].copy_from_slice(&[1, 2, 3]);
+
+        for _ in 0..6 {
+            cpu.tick().unwrap();
+        }
+
+        assert_eq!(cpu.ram[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.ram[0x310..0x313], [3, 2, 1]);
+        assert_eq!(cpu.regs[5..8], [3, 2, 1]);
+        assert_eq!(cpu.index_reg, 0x300);
+    }
+
+    #[test]
+    fn xo_planes() {
+        // Select both planes, V0 = 0, I = 0x300, draw 1 row to each plane
+        let mut cpu = quirks_cpu(
+            QuirkProfile::XoChip,
+            &[0xF3, 0x01, 0x60, 0x00, 0xA3, 0x00, 0xD0, 0x01],
+        );
+        cpu.ram[0x300] = 0b1100_0000;
+        cpu.ram[0x301] = 0b1010_0000;
+
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+
+        assert_eq!(cpu.planes, 3);
+        assert_eq!(cpu.display[0][..4], [3, 1, 2, 0]);
+        assert_eq!(cpu.regs[0xF], 0);
+
+        // Clearing only plane 2 leaves plane 1 alone
+        cpu.ram[0x20C..0x210].copy_from_slice(&[0xF2, 0x01, 0x00, 0xE0]);
+        cpu.pc = 0x20C;
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display[0][..4], [1, 1, 0, 0]);
+
+        // Drawing over plane 1 with only plane 2 selected is no collision
+        cpu.pc = 0x206;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display[0][..4], [3, 3, 0, 0]);
+        assert_eq!(cpu.regs[0xF], 0);
+    }
+
+    #[test]
+    fn xo_scroll_up() {
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &[0x00, 0xD2]);
+        cpu.display[5][1] = 1;
+        cpu.display[0][0] = 1;
+
+        cpu.tick().unwrap();
+
+        assert_eq!(cpu.display[3][1], 1);
+        assert_eq!(cpu.display[5][1], 0);
+        assert_eq!(cpu.display[0][0], 0);
+    }
+
+    #[test]
+    fn xo_audio() {
+        // I = 0x300, load audio pattern, V1 = 200, pitch = V1
+        let mut cpu = quirks_cpu(
+            QuirkProfile::XoChip,
+            &[0xA3, 0x00, 0xF0, 0x02, 0x61, 0xC8, 0xF1, 0x3A],
+        );
+        for i in 0..16 {
+            cpu.ram[0x300 + i] = i as u8;
+        }
+
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+
+        assert_eq!(cpu.audio_pattern, core::array::from_fn(|i| i as u8));
+        assert_eq!(cpu.pitch, 200);
+    }
+
+    #[test]
+    fn parse_audio_args() {
+        let command = args(&[
+            "--tone",
+            "220",
+            "--volume",
+            "50",
+            "--waveform",
+            "sine",
+            "--mute",
+            "game.ch8",
+        ]);
+
+        let mut expected = Config::new("game.ch8");
+        expected.audio = AudioSettings {
+            frequency: 220.0,
+            volume: 0.5,
+            waveform: Waveform::Sine,
+            muted: true,
+        };
+
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert!(args(&["--waveform", "noise", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn tone_generator() {
+        let settings = AudioSettings {
+            frequency: 1.0,
+            volume: 0.5,
+            waveform: Waveform::Square,
+            muted: false,
+        };
+        let mut generator = ToneGenerator::new(settings, 4);
+
+        let samples: Vec<f32> = (0..5).map(|_| generator.next_sample()).collect();
+        assert_eq!(samples, [0.5, 0.5, -0.5, -0.5, 0.5]);
+
+        generator.toggle_mute();
+        assert_eq!(generator.next_sample(), 0.0);
+    }
+
+    #[test]
+    fn recording_audio_follows_sound_timer() {
+        // Sound timer = V0 = 2, then spin
+        let mut machine = quirks_machine(
+            QuirkProfile::CosmacVip,
+            &[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04],
+        );
+        let mut audio = RecordingAudio::new(AudioSettings::default());
+
+        for _ in 0..4 {
+            machine.run_frame().unwrap();
+            audio.update(machine.buzzer_on());
+        }
+
+        let frame = (SAMPLE_RATE / 60) as usize;
+        assert_eq!(audio.samples.len(), frame * 4);
+        assert!(audio.samples[..frame]
+            .iter()
+            .all(|&sample| sample.abs() == 8191));
+        assert!(audio.samples[frame..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn recording_audio_wav() {
+        let mut audio = RecordingAudio::new(AudioSettings::default());
+        audio.update(true);
+
+        let wav = audio.to_wav();
+        let data_len = (SAMPLE_RATE / 60) * 2;
+
+        assert_eq!(wav.len(), 44 + data_len as usize);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], data_len.to_le_bytes());
+        assert_eq!(wav[44..46], 8191i16.to_le_bytes());
+    }
+
+    #[cfg(feature = "sdl")]
+    fn key_down(keycode: Keycode) -> Event {
+        Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: Mod::NOMOD,
+            repeat: false,
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    fn key_up(keycode: Keycode) -> Event {
+        Event::KeyUp {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: Mod::NOMOD,
+            repeat: false,
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keypad_tracks_held_keys() {
+        let mut keypad = KeypadState::new(KeyMap::default());
+
+        assert_eq!(keypad.handle_event(&key_down(Keycode::Num1)), None);
+        assert_eq!(keypad.handle_event(&key_down(Keycode::V)), None);
+        assert_eq!(keypad.keys[0x1], 1);
+        assert_eq!(keypad.keys[0xF], 1);
+
+        // Nothing new this frame, keys stay held
+        assert_eq!(keypad.keys[0x1], 1);
+
+        keypad.handle_event(&key_up(Keycode::Num1));
+        assert_eq!(keypad.keys[0x1], 0);
+        assert_eq!(keypad.keys[0xF], 1);
+
+        keypad.handle_event(&key_down(Keycode::P));
+        keypad.handle_event(&key_up(Keycode::V));
+        assert_eq!(keypad.keys, [0u8; 16]);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keypad_hotkeys() {
+        let mut keypad = KeypadState::new(KeyMap::default());
+
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::Escape)),
+            Some(KeyStroke::Quit)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::Space)),
+            Some(KeyStroke::Next)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F1)),
+            Some(KeyStroke::Mute)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F5)),
+            Some(KeyStroke::Pause)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F6)),
+            Some(KeyStroke::Next)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F7)),
+            Some(KeyStroke::StepOver)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F8)),
+            Some(KeyStroke::StepOut)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F9)),
+            Some(KeyStroke::SaveState)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F10)),
+            Some(KeyStroke::LoadState)
+        );
+        assert_eq!(
+            keypad.handle_event(&key_down(Keycode::F11)),
+            Some(KeyStroke::NextSlot)
+        );
+        assert_eq!(keypad.handle_event(&key_down(Keycode::Backspace)), None);
+        assert!(keypad.rewinding);
+        assert_eq!(keypad.handle_event(&key_up(Keycode::Backspace)), None);
+        assert!(!keypad.rewinding);
+        assert_eq!(
+            keypad.handle_event(&Event::Quit { timestamp: 0 }),
+            Some(KeyStroke::Quit)
+        );
+        assert_eq!(keypad.keys, [0u8; 16]);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn skp_held_key() {
+        // V0 = 0xA, skip if key V0 pressed, skip if key V0 not pressed
+        let program = [0x60, 0x0A, 0xE0, 0x9E, 0x00, 0xE0, 0xE0, 0xA1];
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        let mut keypad = KeypadState::new(KeyMap::default());
+
+        keypad.handle_event(&key_down(Keycode::Z));
+        cpu.set_key(&KeyStroke::Key(keypad.keys));
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+
+        // Held over into the next frame
+        cpu.set_key(&KeyStroke::Key(keypad.keys));
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x208);
+    }
+
+    #[test]
+    fn keymap_presets() {
+        let qwerty = KeyMap::default();
+        assert_eq!(qwerty.hex_key("x"), Some(0x0));
+        assert_eq!(qwerty.hex_key("4"), Some(0xC));
+        assert_eq!(qwerty.hex_key("v"), Some(0xF));
+        assert_eq!(qwerty.hex_key("up"), None);
+
+        let azerty = KeyMap::preset("azerty").unwrap();
+        assert_eq!(azerty.hex_key("a"), Some(0x4));
+        assert_eq!(azerty.hex_key("w"), Some(0xA));
+        assert_eq!(azerty.hex_key("&"), Some(0x1));
+
+        let arrows = KeyMap::preset("arrows").unwrap();
+        assert_eq!(arrows.hex_key("up"), Some(0x5));
+        assert_eq!(arrows.hex_key("w"), Some(0x5));
+
+        for layout in LAYOUTS {
+            assert!(KeyMap::preset(layout).is_some(), "{}", layout);
+        }
+        assert_eq!(KeyMap::preset("bepo"), None);
+    }
+
+    #[test]
+    fn keymap_from_config() {
+        let text = "
+            # Shared by every ROM
+            layout = dvorak
+            5 = w up   # both press 5
+
+            [breakout.ch8]
+            4 = left
+            6 = right
+
+            [other.ch8]
+            layout = qwerty
+        ";
+
+        let keymap = KeyMap::from_config(text, "breakout.ch8", None).unwrap();
+        assert_eq!(keymap.hex_key("a"), Some(0x7));
+        assert_eq!(keymap.hex_key("w"), Some(0x5));
+        assert_eq!(keymap.hex_key("up"), Some(0x5));
+        assert_eq!(keymap.hex_key(","), None);
+        assert_eq!(keymap.hex_key("left"), Some(0x4));
+        assert_eq!(keymap.hex_key("'"), None);
+
+        let keymap = KeyMap::from_config(text, "pong.ch8", None).unwrap();
+        assert_eq!(keymap.hex_key("'"), Some(0x4));
+        assert_eq!(keymap.hex_key("left"), None);
+
+        let keymap = KeyMap::from_config(text, "pong.ch8", Some("colemak")).unwrap();
+        assert_eq!(keymap.hex_key("t"), Some(0xE));
+        assert_eq!(keymap.hex_key("up"), Some(0x5));
+    }
+
+    #[test]
+    fn keymap_config_errors() {
+        let error = |text| KeyMap::from_config(text, "game.ch8", None).unwrap_err();
+
+        assert_eq!(error("layout = bepo"), "line 1: unknown layout 'bepo'");
+        assert_eq!(error("\n10 = q"), "line 2: '10' is not a hex key");
+        assert_eq!(error("q"), "line 1: expected '=' in 'q'");
+        assert_eq!(error("[game.ch8"), "line 1: missing ']'");
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keypad_uses_keymap() {
+        let mut keymap = KeyMap::preset("arrows").unwrap();
+        keymap.bind(0x0, &["kp0", "Return"]);
+        let mut keypad = KeypadState::new(keymap);
+
+        keypad.handle_event(&key_down(Keycode::Up));
+        keypad.handle_event(&key_down(Keycode::Kp0));
+        assert_eq!(keypad.keys[0x5], 1);
+        assert_eq!(keypad.keys[0x0], 1);
+
+        keypad.handle_event(&key_up(Keycode::Kp0));
+        keypad.handle_event(&key_down(Keycode::Return));
+        assert_eq!(keypad.keys[0x0], 1);
+
+        // X no longer presses 0 once 0 is rebound
+        keypad.handle_event(&key_up(Keycode::Return));
+        keypad.handle_event(&key_down(Keycode::X));
+        assert_eq!(keypad.keys[0x0], 0);
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keypad_falls_back_to_scancode() {
+        let mut keypad = KeypadState::new(KeyMap::preset("azerty").unwrap());
+
+        // The AZERTY "é" key has no SDL keycode
+        keypad.handle_event(&Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: None,
+            scancode: Some(Scancode::Num2),
+            keymod: Mod::NOMOD,
+            repeat: false,
+        });
+
+        assert_eq!(keypad.keys[0x2], 1);
+    }
+
+    fn temp_rom(name: &str, len: usize) -> String {
+        let dir = std::env::temp_dir().join(format!("rusteight-roms-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, vec![0x12; len]).unwrap();
+
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn load_rom_errors() {
+        let missing = String::from("./src/ROMS/missing.ch8");
+        assert_eq!(
+            rom_loader::load_rom(missing.clone(), QuirkProfile::CosmacVip),
+            Err(StartupError::FileNotFound(missing))
+        );
+
+        let empty = temp_rom("empty.ch8", 0);
+        assert_eq!(
+            rom_loader::load_rom(empty.clone(), QuirkProfile::CosmacVip),
+            Err(StartupError::EmptyRom(empty.clone()))
+        );
+
+        for (profile, max) in [
+            (QuirkProfile::CosmacVip, 0xE00),
+            (QuirkProfile::Chip48, 0xE00),
+            (QuirkProfile::Schip, 0xE00),
+            (QuirkProfile::XoChip, 0xFE00),
+        ] {
+            assert_eq!(rom_loader::max_rom_size(profile), max);
+
+            let large = temp_rom("large.ch8", max + 1);
+            assert_eq!(
+                rom_loader::load_rom(large.clone(), profile),
+                Err(StartupError::RomTooLarge {
+                    path: large,
+                    size: max + 1,
+                    max,
+                })
+            );
+
+            let largest = temp_rom("largest.ch8", max);
+            assert_eq!(
+                rom_loader::load_rom(largest, profile).unwrap()[0x200 + max - 1],
+                0x12
+            );
+        }
 
+        let directory = String::from("./src/ROMS");
+        assert!(matches!(
+            rom_loader::load_rom(directory, QuirkProfile::CosmacVip),
+            Err(StartupError::Unreadable { .. })
+        ));
+
+        std::fs::remove_dir_all(std::path::Path::new(&empty).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn startup_error_messages() {
+        assert_eq!(
+            StartupError::EmptyRom(String::from("game.ch8")).to_string(),
+            "game.ch8: ROM is empty"
+        );
+        assert_eq!(
+            StartupError::RomTooLarge {
+                path: String::from("game.ch8"),
+                size: 70000,
+                max: 65024
+            }
+            .to_string(),
+            "game.ch8: ROM is 70000 bytes, the most that fits in memory is 65024"
+        );
+        assert_eq!(
+            StartupError::SdlInit(String::from("no video device")).to_string(),
+            "could not start SDL: no video device"
+        );
+        assert_eq!(
+            StartupError::TerminalInit(String::from("not a terminal")).to_string(),
+            "could not set up the terminal: not a terminal"
+        );
+        assert_eq!(
+            StartupError::TerminalKeys(vec![String::from("kp5"), String::from("lshift")])
+                .to_string(),
+            "the terminal cannot read kp5, lshift, bind other keys to use --terminal"
+        );
+        assert_eq!(
+            StartupError::MovieWrongRom(String::from("bug.r8m")).to_string(),
+            "bug.r8m: movie was recorded with a different ROM"
+        );
+    }
+
+    #[test]
+    fn invalid_opcode_fault() {
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0x60, 0x01, 0xFF, 0xFF]);
+
+        cpu.tick().unwrap();
+        assert_eq!(
+            cpu.tick(),
+            Err(CpuFault {
+                pc: 0x202,
+                opcode: 0xFFFF,
+                kind: FaultKind::InvalidOpcode
+            })
+        );
+        // Halted at the faulting instruction
+        assert_eq!(cpu.pc, 0x202);
+        assert!(cpu.tick().is_err());
+
+        cpu.fault_mode = FaultMode::Lenient;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn stack_faults() {
+        // Calls itself forever
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0x22, 0x00]);
+        for _ in 0..16 {
+            cpu.tick().unwrap();
+        }
+        let fault = cpu.tick().unwrap_err();
+        assert_eq!(fault.kind, FaultKind::StackOverflow);
+        assert_eq!(cpu.sp, 16);
+
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0x00, 0xEE]);
+        assert_eq!(cpu.tick().unwrap_err().kind, FaultKind::StackUnderflow);
+
+        cpu.fault_mode = FaultMode::Lenient;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.sp, 0);
+    }
+
+    #[test]
+    fn memory_out_of_range_fault() {
+        // I = 0xFFFE through the long index load, then BCD of V0
+        let program = [0xF0, 0x00, 0xFF, 0xFE, 0xF0, 0x33];
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &program);
+
+        cpu.tick().unwrap();
+        assert_eq!(
+            cpu.tick().unwrap_err(),
+            CpuFault {
+                pc: 0x204,
+                opcode: 0xF033,
+                kind: FaultKind::MemoryOutOfRange(0x10000)
+            }
+        );
+        assert_eq!(cpu.ram[0xFFFE], 0);
+
+        cpu.regs[0] = 123;
+        cpu.fault_mode = FaultMode::Lenient;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.ram[0xFFFE..], [1, 2]);
+        assert_eq!(cpu.ram[0], 3);
+    }
+
+    #[test]
+    fn memory_size_follows_profile() {
+        // I = 0xFFF + 1, then load V0 from I
+        let program = [0xAF, 0xFF, 0x61, 0x01, 0xF1, 0x1E, 0xF0, 0x65];
+        let mut machine = quirks_machine(QuirkProfile::CosmacVip, &program);
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(
+            machine.step().unwrap_err(),
+            CpuFault {
+                pc: 0x206,
+                opcode: 0xF065,
+                kind: FaultKind::MemoryOutOfRange(0x1000)
+            }
+        );
+
+        // Lenient mode wraps around the 4 KiB
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        cpu.fault_mode = FaultMode::Lenient;
+        cpu.ram[0] = 0x42;
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.regs[0], 0x42);
+
+        let mut machine = quirks_machine(QuirkProfile::XoChip, &program);
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn draw_out_of_range_fault() {
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &[0xD0, 0x0F]);
+        cpu.index_reg = 0xFFF8;
+
+        assert_eq!(
+            cpu.tick().unwrap_err().kind,
+            FaultKind::MemoryOutOfRange(0x10006)
+        );
+        assert_eq!(cpu.display, [[0u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT]);
+    }
+
+    #[test]
+    fn machine_stops_on_fault() {
+        let mut machine = quirks_machine(QuirkProfile::CosmacVip, &[0x00, 0x00]);
+        machine.cpu_mut().delay_timer = 5;
+
+        assert!(machine.run_frame().is_err());
+        assert_eq!(machine.cpu().delay_timer, 5);
+        assert_eq!(machine.frame_count(), 0);
+    }
+
+    #[test]
+    fn parse_lenient_arg() {
+        assert_eq!(
+            args(&["--lenient", "game.ch8"]),
+            Ok(Command::Run(Config {
+                lenient: true,
+                ..Config::new("game.ch8")
+            }))
+        );
+    }
+
+    #[test]
+    fn disassemble_follows_code() {
+        // Call a subroutine that loads I with the sprite after it, then loop
+        let rom = [0x22, 0x04, 0x12, 0x02, 0xA2, 0x08, 0x00, 0xEE, 0x3C, 0x42];
+
+        let source = disassemble(&rom, InstructionSet::Chip8);
+        let lines: Vec<&str> = source.lines().collect();
+
+        assert_eq!(lines[0], ": main");
+        assert!(lines[1].starts_with("\t:call code_204 "));
+        assert!(lines[1].ends_with("# 0200  2204"));
+        assert_eq!(lines[2], ": code_202");
+        assert!(lines[3].starts_with("\tjump code_202 "));
+        assert_eq!(lines[4], ": code_204");
+        assert!(lines[5].starts_with("\ti := data_208 "));
+        assert!(lines[6].starts_with("\treturn "));
+        assert_eq!(lines[7], ": data_208");
+        assert!(lines[8].starts_with("\t0x3c 0x42 "));
+        assert_eq!(lines.len(), 9);
+    }
+
+    #[test]
+    fn disassemble_skips_and_instruction_sets() {
+        // Skip over hires, then exit
+        let rom = [0x30, 0x01, 0x00, 0xFF, 0x00, 0xFD];
+
+        let source = disassemble(&rom, InstructionSet::Schip);
+        assert!(source.contains("\tif v0 != 0x01 then "));
+        assert!(source.contains("\thires "));
+        assert!(source.contains("\texit "));
+
+        // Without SUPER-CHIP only the skip is code
+        let source = disassemble(&rom, InstructionSet::Chip8);
+        assert!(source.contains("\tif v0 != 0x01 then "));
+        assert!(source.contains("\t0x00 0xff 0x00 0xfd "));
+        assert_eq!(
+            InstructionSet::from_name("xochip"),
+            Some(InstructionSet::XoChip)
+        );
+        assert_eq!(InstructionSet::from_name("nes"), None);
+    }
+
+    #[test]
+    fn encode_inverts_decode() {
+        for inst in 0..=0xFFFF {
+            let instruction = Instruction::decode(inst);
+            if !matches!(instruction, Instruction::Invalid(_)) {
+                assert_eq!(instruction.encode(), inst, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_program() {
+        let source = "
+            :const SPEED 2
+            : main
+                v0 := SPEED     # Comment
+                v1 -= 1
+                i := ball
+                draw
+                if v0 != v1 then jump main
+                i := long ball
+            : loop
+                jump loop
+            : draw
+                sprite v0 v1 1
+                ;
+            : ball
+                0x80 :byte -1 :word main
+        ";
+
+        let assembly = assemble(source, Path::new("ball.8o")).unwrap();
+
+        assert_eq!(
+            assembly.rom,
+            [
+                0x60, 0x02, 0x71, 0xFF, 0xA2, 0x16, 0x22, 0x12, 0x50, 0x10, 0x12, 0x00, 0xF0, 0x00,
+                0x02, 0x16, 0x12, 0x10, 0xD0, 0x11, 0x00, 0xEE, 0x80, 0xFF, 0x02, 0x00,
+            ]
+        );
+        assert_eq!(assembly.labels["loop"], 0x210);
+        assert!(assembly
+            .symbol_map()
+            .starts_with("main 0x0200\nloop 0x0210\n"));
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let error = |source: &str| assemble(source, Path::new("game.8o")).unwrap_err();
+
+        assert_eq!(
+            error("clear\n  v0 := 256"),
+            AssembleError {
+                file: String::from("game.8o"),
+                line: 2,
+                column: 9,
+                message: String::from("256 is out of range -128..=255"),
+            }
+        );
+        assert_eq!(error("jump nowhere").message, "undefined label 'nowhere'");
+        assert_eq!(error("  i += 5").column, 8);
+        assert_eq!(error(": a : a").message, "'a' is already defined");
+        assert_eq!(
+            error("if v0 <> 3 then").to_string(),
+            "game.8o:1:7: expected a comparison, 'key' or '-key', found '<>'"
+        );
+        assert_eq!(error("v0 :=").message, "unexpected end of file");
+        assert_eq!(
+            error("clear\n loop clear").to_string(),
+            "game.8o:2:2: 'loop' is missing its 'again'"
+        );
+        assert_eq!(error("else").message, "'else' without 'if ... begin'");
+        assert_eq!(error("while v0 == 1").message, "'while' outside a loop");
+        assert_eq!(
+            error(":org 0x100").message,
+            "256 is out of range 512..=65535"
+        );
+        assert_eq!(error(":calc X { 1 + Y }").message, "undefined name 'Y'");
+        assert_eq!(
+            error(":calc X { 1 << 70 }").to_string(),
+            "game.8o:1:13: cannot shift by 70"
+        );
+        assert_eq!(error(":calc X { 1 >> -1 }").message, "cannot shift by -1");
+        assert_eq!(
+            error(":calc X { 1 / 0 }").to_string(),
+            "game.8o:1:13: division by zero"
+        );
+        assert_eq!(error(":calc X { 1 % 0 }").message, "division by zero");
+    }
+
+    /// Runs compiled Octo source until it exits.
+    fn run_octo(source: &str) -> CPU {
+        let assembly = assemble(source, Path::new("test.8o")).unwrap();
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &assembly.rom);
+
+        for _ in 0..10_000 {
+            if cpu.tick().unwrap() == Instruction::Exit {
+                return cpu;
+            }
+        }
+        panic!("program did not exit");
+    }
+
+    #[test]
+    fn octo_control_flow() {
+        let cpu = run_octo(
+            "
+            :alias counter v0
+            :const LIMIT 10
+            :calc HALF { LIMIT / 2 }
+            :macro bump reg { reg += 1 }
+
+            : main
+                counter := 0
+                loop
+                    bump counter
+                    if counter == HALF then v1 += 1
+                    while counter < LIMIT
+                again
+
+                if v1 == 1 begin
+                    v2 := 0xAA
+                else
+                    v2 := 0xBB
+                end
+                if counter >= 10 then v3 := 1
+                if counter > 10 then v4 := 1
+                if counter <= v0 then v5 := 1
+                v6 := 200
+                if v6 > counter then v7 := 1
+                if v6 < counter begin v8 := 1 end
+                exit
+            ",
+        );
+
+        assert_eq!(cpu.regs[0], 10);
+        assert_eq!(cpu.regs[1..9], [1, 0xAA, 1, 0, 1, 200, 1, 0]);
+    }
+
+    #[test]
+    fn octo_directives() {
+        let assembly = assemble(
+            "
+            :calc RIGHT_TO_LEFT { 2 * 3 + 1 }
+            :calc BRACKETED { ( 2 * 3 ) + 1 }
+            : main
+                i := data
+                :byte { RIGHT_TO_LEFT }
+                :byte { BRACKETED }
+                :byte { HERE - 0x200 }
+            :org 0x300
+            : data
+                0x12
+            ",
+            Path::new("org.8o"),
+        )
+        .unwrap();
+
+        assert_eq!(assembly.rom[..5], [0xA3, 0x00, 8, 7, 4]);
+        assert_eq!(assembly.rom.len(), 0x101);
+        assert_eq!(assembly.rom[0x100], 0x12);
+        assert_eq!(assembly.labels["data"], 0x300);
+    }
+
+    #[test]
+    fn arithmetic_flags() {
+        // Carry and borrow land in VF even when it is the target, and an
+        // equal subtraction does not borrow
+        let cpu = run_octo(
+            "
+            vf := 0x10 v1 := 0xF0 vf += v1 v2 := vf
+            v3 := 5 v4 := 5 v3 -= v4 v5 := vf
+            v6 := 2 v7 := 5 v6 =- v7 v8 := vf
+            v9 := 5 va := 2 v9 =- va vb := vf
+            exit
+            ",
+        );
+
+        assert_eq!(cpu.regs[2], 1);
+        assert_eq!((cpu.regs[3], cpu.regs[5]), (0, 1));
+        assert_eq!((cpu.regs[6], cpu.regs[8]), (3, 1));
+        assert_eq!((cpu.regs[9], cpu.regs[0xB]), (253, 0));
+    }
+
+    #[test]
+    fn load_octo_source() {
+        let dir = std::env::temp_dir().join(format!("rusteight-octo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let game = dir.join("game.8o");
+        std::fs::write(&game, ": main loop again").unwrap();
+        let ram =
+            rom_loader::load_rom(game.to_string_lossy().into_owned(), QuirkProfile::CosmacVip)
+                .unwrap();
+        assert_eq!(ram[0x200..0x202], [0x12, 0x00]);
+        assert_eq!(ram[rom_loader::FONT_ADDR], 0xF0);
+
+        let broken = dir.join("broken.8o");
+        std::fs::write(&broken, "jump nowhere").unwrap();
+        let error = rom_loader::load_rom(
+            broken.to_string_lossy().into_owned(),
+            QuirkProfile::CosmacVip,
+        )
+        .unwrap_err();
+        assert!(matches!(error, StartupError::Compile(_)));
+        assert!(error
+            .to_string()
+            .ends_with("broken.8o:1:6: undefined label 'nowhere'"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn assemble_includes() {
+        let dir = std::env::temp_dir().join(format!("rusteight-asm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("sprite.8o"), ": sprite-data 0xF0 0x90").unwrap();
+        std::fs::write(dir.join("loop.8o"), ":include \"loop.8o\"").unwrap();
+
+        let main = dir.join("main.8o");
+        let assembly = assemble("i := sprite-data :include \"sprite.8o\"", &main).unwrap();
+        assert_eq!(assembly.rom, [0xA2, 0x02, 0xF0, 0x90]);
+
+        let error = assemble(":include \"loop.8o\"", &main).unwrap_err();
+        assert!(error.message.ends_with("is included recursively"));
+        assert!(error.file.ends_with("loop.8o"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn disassembly_reassembles() {
+        let xochip = [
+            0x00, 0xFF, 0xF0, 0x00, 0x02, 0x0C, 0xF2, 0x01, 0xD0, 0x00, 0x12, 0x0A, 0xAA, 0x55,
+        ];
+        let roms = [
+            std::fs::read("src/ROMS/IBM.ch8").unwrap(),
+            std::fs::read("src/ROMS/breakout.ch8").unwrap(),
+            xochip.to_vec(),
+        ];
+
+        for rom in roms {
+            let source = disassemble(&rom, InstructionSet::XoChip);
+            let assembly = assemble(&source, Path::new("rom.8o")).unwrap();
+            assert_eq!(assembly.rom, rom, "{}", source);
+        }
+    }
+
+    #[test]
+    fn parse_debug_commands() {
+        assert_eq!(DebugCommand::parse(" n "), Ok(DebugCommand::StepOver));
+        assert_eq!(
+            DebugCommand::parse("until 20a"),
+            Ok(DebugCommand::RunTo(0x20A))
+        );
+        assert_eq!(
+            DebugCommand::parse("b 0x208 if v3 >= 0x10"),
+            Ok(DebugCommand::Break(Breakpoint {
+                addr: 0x208,
+                condition: Some(RegCondition {
+                    x: 3,
+                    compare: Compare::Ge,
+                    value: 16,
+                }),
+            }))
+        );
+        assert_eq!(
+            DebugCommand::parse("b 0x208 if i == 1"),
+            Err(String::from("expected a register, found 'i'"))
+        );
+        assert_eq!(
+            DebugCommand::parse("jump"),
+            Err(String::from("unknown command 'jump', try 'help'"))
+        );
+    }
+
+    /// Runs frames until the debugger pauses.
+    fn run_to_break(debugger: &mut Debugger, machine: &mut Machine) {
+        for _ in 0..100 {
+            if machine
+                .run_frame_until(|cpu| debugger.should_break(cpu))
+                .unwrap()
+            {
+                return;
+            }
+        }
+        panic!("debugger did not pause");
+    }
+
+    #[test]
+    fn debugger_breakpoints() {
+        // 0x202 counts in v0, 0x204 calls the subroutine at 0x208
+        let assembly = assemble(
+            ": main v0 := 0 loop v0 += 1 sub again : sub v1 := v0 return",
+            Path::new("debug.8o"),
+        )
+        .unwrap();
+        let mut machine = quirks_machine(QuirkProfile::CosmacVip, &assembly.rom);
+        let mut debugger = Debugger::default();
+
+        let apply = |debugger: &mut Debugger, machine: &mut Machine, line: &str| {
+            debugger.apply(DebugCommand::parse(line).unwrap(), machine.cpu_mut())
+        };
+
+        apply(&mut debugger, &mut machine, "b 208 if v0 == 3");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().pc, machine.cpu().regs[0]), (0x208, 3));
+        assert!(debugger.paused);
+        assert_eq!(
+            debugger.break_reason(machine.cpu()),
+            "breakpoint 0x0208 if v0 == 3, paused at 0x0208  LD V1, V0"
+        );
+
+        apply(&mut debugger, &mut machine, "d 208");
+        apply(&mut debugger, &mut machine, "b 202");
+        apply(&mut debugger, &mut machine, "c");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().pc, machine.cpu().regs[0]), (0x202, 3));
+
+        // Continuing from a breakpoint runs round the loop back to it
+        apply(&mut debugger, &mut machine, "c");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().pc, machine.cpu().regs[0]), (0x202, 4));
+
+        apply(&mut debugger, &mut machine, "s");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!(
+            debugger.break_reason(machine.cpu()),
+            "paused at 0x0204  CALL 0x208"
+        );
+        apply(&mut debugger, &mut machine, "n");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!(
+            (machine.cpu().pc, machine.cpu().sp, machine.cpu().regs[1]),
+            (0x206, 0, 5)
+        );
+
+        assert_eq!(apply(&mut debugger, &mut machine, "breakpoints"), "0x0202");
+        apply(&mut debugger, &mut machine, "d 202");
+        apply(&mut debugger, &mut machine, "until 204");
+        run_to_break(&mut debugger, &mut machine);
+        apply(&mut debugger, &mut machine, "s");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().pc, machine.cpu().sp), (0x208, 1));
+        apply(&mut debugger, &mut machine, "finish");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().pc, machine.cpu().sp), (0x206, 0));
+
+        assert_eq!(
+            apply(&mut debugger, &mut machine, "finish"),
+            "not in a subroutine"
+        );
+        assert_eq!(
+            apply(&mut debugger, &mut machine, "breakpoints"),
+            "no breakpoints"
+        );
+    }
+
+    #[test]
+    fn debugger_watchpoints() {
+        let assembly = assemble(
+            "
+            : main
+                i := score
+                v0 := 123
+                bcd v0
+                load v2
+                loop v1 += 1 again
+            : score 0 0 0
+            ",
+            Path::new("watch.8o"),
+        )
+        .unwrap();
+        let mut machine = quirks_machine(QuirkProfile::XoChip, &assembly.rom);
+        let mut debugger = Debugger::default();
+        let apply = |debugger: &mut Debugger, machine: &mut Machine, line: &str| {
+            debugger.apply(DebugCommand::parse(line).unwrap(), machine.cpu_mut())
+        };
+
+        assert_eq!(
+            apply(&mut debugger, &mut machine, "watch 20d"),
+            "watch 0x020d w"
+        );
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!(
+            debugger.break_reason(machine.cpu()),
+            "watchpoint: write 0x020d by 0x0204  LD B, V0, paused at 0x0206  LD V2, [I]"
+        );
+
+        apply(&mut debugger, &mut machine, "unwatch 20d");
+        apply(&mut debugger, &mut machine, "w 20c-20e r");
+        apply(&mut debugger, &mut machine, "s");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!(
+            debugger.break_reason(machine.cpu()),
+            "watchpoint: read 0x020c by 0x0206  LD V2, [I], paused at 0x0208  ADD V1, 0x01"
+        );
+
+        apply(&mut debugger, &mut machine, "unwatch 20c-20e r");
+        apply(&mut debugger, &mut machine, "watch v1");
+        apply(&mut debugger, &mut machine, "c");
+        run_to_break(&mut debugger, &mut machine);
+        assert_eq!(machine.cpu().pc, 0x20A);
+        assert!(debugger
+            .break_reason(machine.cpu())
+            .starts_with("watchpoint: write v1 by 0x0208"));
+        assert_eq!(
+            apply(&mut debugger, &mut machine, "breakpoints"),
+            "watch v1"
+        );
+
+        assert_eq!(
+            DebugCommand::parse("watch 300 x"),
+            Err(String::from("expected r, w or rw, found 'x'"))
+        );
+    }
+
+    #[test]
+    fn parse_debug_arg() {
+        assert_eq!(
+            args(&["--debug", "game.ch8"]),
+            Ok(Command::Run(Config {
+                debug: true,
+                ..Config::new("game.ch8")
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_trace_args() {
+        assert_eq!(
+            args(&[
+                "--trace",
+                "-",
+                "--trace-range",
+                "200-2ff",
+                "--trace-last",
+                "50",
+                "a.ch8"
+            ]),
+            Ok(Command::Run(Config {
+                trace: TraceSettings {
+                    path: Some(String::from("-")),
+                    range: Some((0x200, 0x2FF)),
+                    last: Some(50),
+                },
+                ..Config::new("a.ch8")
+            }))
+        );
+        assert_eq!(
+            args(&["--trace-range", "300-200", "a.ch8"]),
+            Err(String::from("invalid address range '300-200'"))
+        );
+    }
+
+    /// Trace output kept where the test can read it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    /// Ticks the CPU, tracing each instruction, until it faults.
+    fn trace_until_fault(settings: &TraceSettings, program: &[u8]) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(settings, Box::new(buffer.clone()));
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, program);
+
+        loop {
+            tracer.record(&cpu);
+            if cpu.tick().is_err() {
+                tracer.dump();
+                return buffer.lines();
+            }
+        }
+    }
+
+    #[test]
+    fn trace_lines() {
+        // V0 = 0x12, I = 0x300, V0 += 1, return with nothing on the stack
+        let program = [0x60, 0x12, 0xA3, 0x00, 0x70, 0x01, 0x00, 0xEE];
+
+        let lines = trace_until_fault(&TraceSettings::default(), &program);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "PC:0200 OP:6012 V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 \
+             V8:00 V9:00 VA:00 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 SP:00 DT:00 ST:00 \
+             CY:1 LD V0, 0x12"
+        );
+        assert!(lines[3].starts_with("PC:0206 OP:00EE V0:13 V1:00"));
+        assert!(lines[3].ends_with("I:0300 SP:00 DT:00 ST:00 CY:4 RET"));
+
+        let range = TraceSettings {
+            range: Some((0x202, 0x204)),
+            ..TraceSettings::default()
+        };
+        let lines = trace_until_fault(&range, &program);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("PC:0202 OP:A300"));
+        assert!(lines[0].ends_with("CY:2 LD I, 0x300"));
+
+        // Only the instructions leading up to the fault are written
+        let last = TraceSettings {
+            last: Some(2),
+            ..TraceSettings::default()
+        };
+        let lines = trace_until_fault(&last, &program);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("PC:0206 OP:00EE"));
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let rom = std::fs::read("src/ROMS/breakout.ch8").unwrap();
+        let mut machine = quirks_machine(QuirkProfile::Schip, &rom);
+        let rom_hash = machine.rom_hash();
+
+        for _ in 0..30 {
+            machine.run_frame().unwrap();
+        }
+        let cpu = machine.cpu_mut();
+        cpu.key_wait = Some(3);
+        cpu.rpl[0] = 7;
+        let state = savestate::save(cpu, rom_hash);
+        let (pc, display) = (cpu.pc, cpu.display);
+
+        cpu.key_wait = None;
+        for _ in 0..30 {
+            machine.run_frame().unwrap();
+        }
+        let cpu = machine.cpu_mut();
+        cpu.watching = true;
+        cpu.fault_mode = FaultMode::Lenient;
+        savestate::restore(cpu, &state, rom_hash).unwrap();
+
+        assert_eq!(cpu.pc, pc);
+        assert_eq!(cpu.display, display);
+        assert_eq!(cpu.key_wait, Some(3));
+        assert_eq!(cpu.quirks, Quirks::preset(QuirkProfile::Schip));
+        assert!(cpu.watching);
+        assert_eq!(cpu.fault_mode, FaultMode::Lenient);
+        assert_eq!(savestate::save(cpu, rom_hash), state);
+    }
+
+    #[test]
+    fn save_state_errors() {
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0x12, 0x00]);
+        let state = savestate::save(&cpu, 1);
+        let restore = |cpu: &mut CPU, bytes: &[u8]| savestate::restore(cpu, bytes, 1);
+
+        assert_eq!(
+            savestate::restore(&mut cpu, &state, 2),
+            Err(StateError::WrongRom)
+        );
+        assert_eq!(
+            restore(&mut cpu, &state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(restore(&mut cpu, b"PNG"), Err(StateError::NotAState));
+
+        let mut future = state.clone();
+        future[4] = 99;
+        assert_eq!(
+            restore(&mut cpu, &future),
+            Err(StateError::UnsupportedVersion(99))
+        );
+        assert_eq!(
+            StateError::WrongRom.to_string(),
+            "save state is for a different ROM"
+        );
+    }
+
+    #[test]
+    fn corrupted_save_state() {
+        let mut cpu = quirks_cpu(QuirkProfile::XoChip, &[0x12, 0x00]);
+        cpu.planes = 0b10;
+        cpu.key_wait = Some(0xF);
+        let state = savestate::save(&cpu, 1);
+        let (planes, key) = (state.len() - 108, state.len() - 51);
+        assert_eq!((state[planes], state[key]), (0b10, 0xF));
+
+        let corrupt = |at: usize, value: u8| {
+            let mut bytes = state.clone();
+            bytes[at] = value;
+            let mut loaded = quirks_cpu(QuirkProfile::XoChip, &[0x12, 0x00]);
+            savestate::restore(&mut loaded, &bytes, 1).map(|()| loaded)
+        };
+
+        assert_eq!(corrupt(planes, 0b11).unwrap().planes, 0b11);
+        assert_eq!(corrupt(planes, 0b100).err(), Some(StateError::NotAState));
+        assert_eq!(corrupt(key, 0).unwrap().key_wait, Some(0));
+        assert_eq!(corrupt(key, 16).err(), Some(StateError::NotAState));
+    }
+
+    #[test]
+    fn save_state_paths() {
+        let path = storage::state_path(0xABC, 3);
+
+        assert_eq!(path.file_name().unwrap(), "0000000000000abc.3.state");
+        assert!(path.parent().unwrap().ends_with("states"));
+        assert_ne!(savestate::hash(&[1, 2]), savestate::hash(&[2, 1]));
+    }
+
+    #[test]
+    fn rewind_frames() {
+        let rom = std::fs::read("src/ROMS/breakout.ch8").unwrap();
+        let mut machine = quirks_machine(QuirkProfile::CosmacVip, &rom);
+        let rom_hash = machine.rom_hash();
+        let mut rewind = Rewind::new(1, rom_hash);
+
+        let mut states = vec![machine.save_state()];
+        rewind.push(machine.cpu());
+        for _ in 0..100 {
+            machine.run_frame().unwrap();
+            states.push(machine.save_state());
+            rewind.push(machine.cpu());
+        }
+        let cpu = machine.cpu_mut();
+        assert_eq!(rewind.frames(), 60);
+
+        // The newest state is where the CPU already is
+        states.pop();
+        for _ in 0..60 {
+            assert!(rewind.step_back(cpu));
+            assert_eq!(savestate::save(cpu, rom_hash), states.pop().unwrap());
+        }
+        assert!(!rewind.step_back(cpu));
+        assert_eq!(states.len(), 40);
+
+        rewind.clear();
+        rewind.push(cpu);
+        assert!(!rewind.step_back(cpu));
+        assert_eq!(Rewind::new(0, rom_hash).frames(), 0);
+    }
+
+    #[test]
+    fn rewind_deltas() {
+        let before: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        let mut after = before.clone();
+        after[0] ^= 1;
+        after[2] ^= 0xFF;
+        after[300] = 0;
+        after[4999] = 7;
+
+        let delta = rewind::encode_delta(&before, &after);
+        assert!(delta.len() < 20);
+        assert_eq!(rewind::encode_delta(&before, &before).len(), 3);
+
+        let mut state = after.clone();
+        rewind::apply_delta(&mut state, &delta);
+        assert_eq!(state, before);
+        rewind::apply_delta(&mut state, &delta);
+        assert_eq!(state, after);
+    }
+
+    #[test]
+    fn parse_rewind_arg() {
+        assert_eq!(
+            args(&["--rewind", "30", "a.ch8"]),
+            Ok(Command::Run(Config {
+                rewind_seconds: 30,
+                ..Config::new("a.ch8")
+            }))
+        );
+        assert_eq!(Config::new("a.ch8").rewind_seconds, 10);
+    }
+
+    #[test]
+    fn seeded_random() {
+        // V0 := random 0xFF, jump back
+        let program = [0xC0, 0xFF, 0x12, 0x00];
+        let run = |seed: u64| {
+            let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+            cpu.rng = Rng::new(seed);
+            (0..2000)
+                .map(|_| {
+                    cpu.tick().unwrap();
+                    cpu.tick().unwrap();
+                    cpu.regs[0]
+                })
+                .collect::<Vec<u8>>()
+        };
+
+        let values = run(42);
+        assert_eq!(values, run(42));
+        assert_ne!(values, run(43));
+        assert!(values.contains(&0xFF));
+        assert!(values.contains(&0));
+    }
+
+    #[test]
+    fn save_state_keeps_rng() {
+        // V0 := random 0xFF
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &[0xC0, 0xFF]);
+        cpu.rng = Rng::new(7);
+        let state = savestate::save(&cpu, 0);
+
+        cpu.tick().unwrap();
+        let value = cpu.regs[0];
+        savestate::restore(&mut cpu, &state, 0).unwrap();
+        cpu.regs[0] = 0;
+        cpu.tick().unwrap();
+
+        assert_eq!(cpu.regs[0], value);
+        assert_eq!(
+            args(&["--seed", "1234", "a.ch8"]),
+            Ok(Command::Run(Config {
+                seed: Some(1234),
+                ..Config::new("a.ch8")
+            }))
+        );
+    }
+
+    /// V0 := random 0xFF, V1 counts frames where key 5 is held.
+    const MOVIE_PROGRAM: [u8; 10] = [0xC0, 0xFF, 0x62, 0x05, 0xE2, 0xA1, 0x71, 0x01, 0x12, 0x00];
+
+    /// Runs a movie's input from a fresh start seeded with `seed`, returning
+    /// the first desynced frame if any.
+    fn play_movie(movie: &Movie, seed: u64) -> (Machine, Option<usize>) {
+        let mut machine = quirks_machine(movie.quirks, &MOVIE_PROGRAM);
+        machine.set_seed(seed);
+        machine.set_instructions_per_frame(movie.instructions_per_frame);
+        let mut playback = Playback::new(movie.clone());
+        let mut desync = None;
+
+        while !playback.finished() {
+            machine.set_keys(playback.keys());
+            machine.run_frame().unwrap();
+            if let Err(frame) = playback.advance(machine.cpu()) {
+                desync = desync.or(Some(frame));
+            }
+        }
+        (machine, desync)
+    }
+
+    #[test]
+    fn movie_round_trip() {
+        let mut machine = quirks_machine(QuirkProfile::Chip48, &MOVIE_PROGRAM);
+        machine.set_seed(99);
+        machine.set_instructions_per_frame(7);
+        let rom_hash = machine.rom_hash();
+        let mut movie = Movie::new(rom_hash, 99, QuirkProfile::Chip48, 7, [3; 16]);
+
+        for frame in 0..150 {
+            let mut keys = [0u8; 16];
+            keys[5] = (frame % 7 < 3) as u8;
+            keys[0xF] = (frame == 100) as u8;
+            machine.set_keys(keys);
+            machine.run_frame().unwrap();
+            movie.record(machine.cpu());
+        }
+        assert_eq!(movie.frames.len(), 150);
+        assert_eq!(movie.checks.len(), 2);
+
+        let text = movie.to_text();
+        assert!(text.contains("\nquirks chip48\nipf 7\n"));
+        assert!(text.contains("\n8020\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie.clone()));
+
+        let (played, desync) = play_movie(&movie, 99);
+        assert_eq!(desync, None);
+        assert_eq!(played.cpu().regs, machine.cpu().regs);
+        assert_eq!(
+            movie::state_hash(played.cpu(), rom_hash),
+            movie::state_hash(machine.cpu(), rom_hash)
+        );
+
+        assert_eq!(play_movie(&movie, 100).1, Some(60));
+    }
+
+    #[test]
+    fn movie_errors() {
+        let header = "rusteight movie 1\nrom 00000000000000ab\nseed 1\nquirks vip\nipf 10\n\
+                      flags 00000000000000000000000000000000\n";
+        let movie = Movie::parse(&format!("{}0001\n\n0020\n", header)).unwrap();
+        assert_eq!(movie.rom_hash, 0xAB);
+        assert_eq!(movie.frames[1][5], 1);
+
+        assert_eq!(
+            Movie::parse("rusteight state"),
+            Err(String::from("not a rusteight movie"))
+        );
+        assert_eq!(
+            Movie::parse(&header.replace("ipf 10", "ipf ten")),
+            Err(String::from("invalid ipf 'ten'"))
+        );
+        assert_eq!(
+            Movie::parse(&header.replace("seed", "sed")),
+            Err(String::from("line 3: expected 'seed'"))
+        );
+        assert_eq!(
+            Movie::parse(&format!("{}0001\nxyz\n", header)),
+            Err(String::from("line 8: invalid key mask 'xyz'"))
+        );
+    }
+
+    #[test]
+    fn input_script() {
+        let script = InputScript::parse("# frame keys\n10 5 a\n\n20 F # jump\n30\n").unwrap();
+        let mut held = [0u8; 16];
+
+        assert_eq!(script.keys(0), held);
+        held[0x5] = 1;
+        held[0xA] = 1;
+        assert_eq!(script.keys(10), held);
+        assert_eq!(script.keys(19), held);
+        assert_eq!(script.keys(25)[0xF], 1);
+        assert_eq!(script.keys(25)[0x5], 0);
+        assert_eq!(script.keys(1000), [0u8; 16]);
+
+        let error = |text| InputScript::parse(text).unwrap_err();
+        assert_eq!(
+            error("10 5\n5 6"),
+            "line 2: frame 5 is not after the line before"
+        );
+        assert_eq!(error("10 10"), "line 1: '10' is not a hex key");
+        assert_eq!(
+            error("soon 1"),
+            "line 1: expected a frame number, found 'soon'"
+        );
+    }
+
+    #[test]
+    fn headless_report() {
+        // V3 := 7, draw the 0 digit at (0, 0)
+        let program = [0x63, 0x07, 0xF0, 0x29, 0xD0, 0x05];
+        let mut cpu = quirks_cpu(QuirkProfile::CosmacVip, &program);
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+
+        let report = headless::report(&cpu, 0, 12, "exited");
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 32 + 5);
+        assert!(lines[0].starts_with("####...."));
+        assert_eq!(lines[0].len(), 64);
+        assert_eq!(lines[32], "frames 12, exited");
+        assert!(lines[33].contains("V3 07"));
+        assert_eq!(
+            lines[36],
+            format!("hash {:016x}", movie::state_hash(&cpu, 0))
+        );
+    }
+
+    #[test]
+    fn parse_headless_args() {
+        assert_eq!(
+            args(&[
+                "--headless",
+                "--until",
+                "2a0 if vf != 0",
+                "--input",
+                "keys.txt",
+                "a.ch8"
+            ]),
+            Ok(Command::Run(Config {
+                headless: true,
+                until: Some(Breakpoint {
+                    addr: 0x2A0,
+                    condition: Some(RegCondition {
+                        x: 0xF,
+                        compare: Compare::Ne,
+                        value: 0,
+                    }),
+                }),
+                input_path: Some(String::from("keys.txt")),
+                ..Config::new("a.ch8")
+            }))
+        );
+        assert_eq!(
+            args(&["--headless", "--until", "2a0 if", "a.ch8"]),
+            Err(String::from(
+                "invalid condition '2a0 if': expected ADDR [if vX OP N], found '2a0 if'"
+            ))
+        );
+        assert!(args(&["--headless", "--input", "a", "--play", "b", "a.ch8"]).is_err());
+    }
+
+    #[test]
+    fn machine_runs_frames() {
+        let rom = Rom::from_bytes(MOVIE_PROGRAM.to_vec(), QuirkProfile::Chip48).unwrap();
+        let mut machine = Machine::new(&rom, Quirks::preset(QuirkProfile::Chip48));
+        machine.set_seed(5);
+        machine.set_instructions_per_frame(5);
+
+        let mut keys = [0u8; 16];
+        keys[5] = 1;
+        machine.set_keys(keys);
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+        }
+
+        assert_eq!(machine.frame_count(), 3);
+        assert_eq!(machine.cpu().regs[1], 3);
+        assert_eq!(machine.rom_hash(), rom.hash());
+        assert_eq!(machine.screen_size(), (64, 32));
+        assert!(!machine.buzzer_on());
+
+        // A frame stopped early is not counted
+        assert!(machine.run_frame_until(|cpu| cpu.pc == 0x204).unwrap());
+        assert_eq!(machine.frame_count(), 3);
+        assert_eq!(machine.cpu().pc, 0x204);
+
+        let state = machine.save_state();
+        let hash = machine.state_hash();
+        machine.step().unwrap();
+        assert_ne!(machine.state_hash(), hash);
+        machine.load_state(&state).unwrap();
+        assert_eq!(machine.state_hash(), hash);
+
+        let other = Rom::from_bytes(vec![0x00, 0xE0], QuirkProfile::Chip48).unwrap();
+        assert_eq!(
+            Machine::new(&other, Quirks::preset(QuirkProfile::Chip48)).load_state(&state),
+            Err(StateError::WrongRom)
+        );
+    }
+
+    #[test]
+    fn movie_recorded_across_breakpoint_plays_back() {
+        let rom = Rom::from_bytes(MOVIE_PROGRAM.to_vec(), QuirkProfile::Chip48).unwrap();
+        let new_machine = || {
+            let mut machine = Machine::new(&rom, Quirks::preset(QuirkProfile::Chip48));
+            machine.set_seed(3);
+            machine.set_instructions_per_frame(7);
+            machine
+        };
+        let mut machine = new_machine();
+        let mut movie = Movie::new(rom.hash(), 3, QuirkProfile::Chip48, 7, [0; 16]);
+
+        let mut keys = [0u8; 16];
+        for frame in 0..120 {
+            keys[5] = (frame % 3 == 0) as u8;
+            machine.set_keys(keys);
+            if frame == 50 {
+                // Stop after 3 of the frame's 7 instructions, as a breakpoint would
+                let mut checked = 0;
+                let stopped = machine.run_frame_until(|_| {
+                    checked += 1;
+                    checked == 4
+                });
+                assert!(stopped.unwrap());
+                assert!(machine.mid_frame());
+                assert_eq!(machine.frame_count(), 50);
+            }
+            machine.run_frame().unwrap();
+            movie.record(machine.cpu());
+        }
+        assert_eq!(machine.frame_count(), 120);
+
+        let config = Config {
+            headless: true,
+            frames: 120,
+            instructions_per_frame: 7,
+            ..Config::new("keys")
+        };
+        let mut played = new_machine();
+        let mut input = headless::Input::Movie(Playback::new(movie));
+        headless::run(
+            &mut played,
+            &config,
+            Path::new("unused.flags"),
+            &mut None,
+            &mut input,
+        )
+        .unwrap();
+        assert_eq!(played.state_hash(), machine.state_hash());
+    }
+
+    #[test]
+    fn rom_from_bytes() {
+        let rom = Rom::from_bytes(vec![0x12, 0x00], QuirkProfile::Chip48).unwrap();
+
+        assert_eq!(rom.program(), [0x12, 0x00]);
+        assert_eq!(rom.memory()[0x200..0x203], [0x12, 0x00, 0x00]);
+        assert_eq!(rom.memory()[rom_loader::FONT_ADDR], 0xF0);
+        assert_eq!(
+            Rom::from_bytes(Vec::new(), QuirkProfile::Chip48),
+            Err(StartupError::EmptyRom(String::from("program")))
+        );
+    }
+
+    #[test]
+    fn frontend_with_memory_devices() {
+        let rom = Rom::from_bytes(MOVIE_PROGRAM.to_vec(), QuirkProfile::Chip48).unwrap();
+        let mut machine = Machine::new(&rom, Quirks::preset(QuirkProfile::Chip48));
+        machine.set_instructions_per_frame(5);
+        let config = Config::new("keys");
+
+        let mut keys = [0u8; 16];
+        keys[5] = 1;
+        let held = || InputFrame {
+            keys,
+            ..InputFrame::default()
+        };
+        let mut input = QueuedInput::new(vec![
+            held(),
+            held(),
+            held(),
+            InputFrame {
+                hotkey: Some(KeyStroke::Pause),
+                ..held()
+            },
+        ]);
+        let mut video = MemoryVideo::default();
+        let mut audio = RecordingAudio::new(AudioSettings::default());
+
+        frontend::run(
+            &mut machine,
+            ManualClock {
+                now: Rc::new(Cell::new(Duration::ZERO)),
+            },
+            &config,
+            Path::new("unused.flags"),
+            None,
+            None,
+            Frontend {
+                video: &mut video,
+                input: &mut input,
+                audio: &mut audio,
+            },
+        );
+
+        // The paused fourth loop presents a frame without running one
+        assert_eq!(machine.cpu().regs[1], 3);
+        assert_eq!(video.frames.len(), 4);
+        assert_eq!(video.status.as_deref(), Some("Paused at 0x0200"));
+        assert_eq!(audio.samples.len(), 4 * (SAMPLE_RATE / 60) as usize);
+    }
+
+    #[test]
+    fn frontend_plays_movie_through_debugger_hotkeys() {
+        let rom = Rom::from_bytes(MOVIE_PROGRAM.to_vec(), QuirkProfile::Chip48).unwrap();
+        let mut machine = Machine::new(&rom, Quirks::preset(QuirkProfile::Chip48));
+        machine.set_instructions_per_frame(5);
+        let config = Config::new("keys");
+        let mut movie = Movie::new(rom.hash(), 0, QuirkProfile::Chip48, 5, [0; 16]);
+        let mut keys = [0u8; 16];
+        keys[5] = 1;
+        movie.frames = vec![keys; 4];
+
+        let hotkey = |hotkey| InputFrame {
+            hotkey: Some(hotkey),
+            ..InputFrame::default()
+        };
+        let mut input = QueuedInput::new(vec![
+            hotkey(KeyStroke::Pause),
+            hotkey(KeyStroke::Next),
+            hotkey(KeyStroke::StepOver),
+            hotkey(KeyStroke::StepOut),
+        ]);
+        let mut video = MemoryVideo::default();
+        let mut audio = NullAudio;
+
+        frontend::run(
+            &mut machine,
+            ManualClock {
+                now: Rc::new(Cell::new(Duration::ZERO)),
+            },
+            &config,
+            Path::new("unused.flags"),
+            None,
+            Some(Playback::new(movie)),
+            Frontend {
+                video: &mut video,
+                input: &mut input,
+                audio: &mut audio,
+            },
+        );
+
+        // Every frame of the movie ran with its keys despite the hotkeys
+        assert_eq!(machine.cpu().regs[1], 4);
+        assert_eq!(video.frames.len(), 4);
+        assert_eq!(video.status, None);
+        assert_eq!(video.messages.len(), 5);
+        assert_eq!(
+            video.messages[0],
+            "the debugger cannot stop a movie being played"
+        );
+        assert_eq!(
+            video.messages[4],
+            "movie finished, the keyboard is live again"
+        );
+    }
+
+    #[test]
+    fn input_sources() {
+        let mut input = QueuedInput::new(vec![InputFrame {
+            rewinding: true,
+            ..InputFrame::default()
+        }]);
+        assert!(input.poll(0).rewinding);
+        assert_eq!(input.poll(1).hotkey, Some(KeyStroke::Quit));
+
+        let mut script = InputScript::parse("0 1\n2 a").unwrap();
+        assert_eq!(script.poll(1).keys[1], 1);
+        assert_eq!(script.poll(2).keys[0xA], 1);
+        assert_eq!(script.poll(2).hotkey, None);
+    }
+
+    /// Text of a rendered line without its colour escapes.
+    fn strip_colours(line: &str) -> String {
+        let mut text = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn terminal_render() {
+        let palette = Palette::from_name("green").unwrap();
+        let mut display = [[0u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        display[0][0] = 1;
+        display[0][1] = 1;
+        display[1][1] = 1;
+        display[3][1] = 2;
+
+        let lines = cells::render(&display, (64, 32), CellStyle::HalfBlocks, &palette);
+        assert_eq!(lines.len(), 16);
+        assert!(lines[0].starts_with("\x1b[38;2;0;255;0m\x1b[48;2;0;0;0m▀\x1b[48;2;0;255;0m "));
+        assert_eq!(strip_colours(&lines[0]), format!("▀ {}", " ".repeat(62)));
+        assert_eq!(strip_colours(&lines[1]).chars().nth(1), Some('▀'));
+        assert!(lines[1].contains("\x1b[38;2;0;0;0m\x1b[48;2;0;110;255m▀"));
+
+        let lines = cells::render(&display, (64, 32), CellStyle::Braille, &palette);
+        assert_eq!(lines.len(), 8);
+        // Plane 2 wins the cell's one colour over plane 1
+        assert!(lines[0].starts_with("\x1b[38;2;0;110;255m\x1b[48;2;0;0;0m\u{2899}"));
+        assert_eq!(strip_colours(&lines[0]).chars().count(), 32);
+        assert_eq!(strip_colours(&lines[1]), "\u{2800}".repeat(32));
+    }
+
+    #[test]
+    fn terminal_screen() {
+        let palette = Palette::from_name("green").unwrap();
+        let mut cpu = CPU::init_cpu();
+        cpu.regs[3] = 0x07;
+        cpu.index_reg = 0x123;
+
+        let messages = [String::from("saved state to slot 0")];
+        let lines = cells::screen(
+            &cpu,
+            Some("Paused"),
+            &messages,
+            CellStyle::Braille,
+            &palette,
+        );
+        assert_eq!(lines.len(), 16);
+        assert!(lines[3].ends_with("  V3 07  VB 00"));
+        assert!(lines[9].ends_with("  I  0123  SP 0"));
+        assert!(lines[11].contains("PC 0x0200"));
+        // Rows below the display keep the panel lined up
+        assert!(lines[13].starts_with(&format!("{}  Paused", " ".repeat(32))));
+        assert!(lines[15].ends_with("  saved state to slot 0"));
+
+        let lines = cells::screen(&cpu, None, &[], CellStyle::HalfBlocks, &palette);
+        assert_eq!(lines.len(), 16);
+        assert_eq!(strip_colours(&lines[12]), " ".repeat(66));
+    }
+
+    #[cfg(feature = "terminal")]
+    fn key_event(code: KeyCode, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent {
+            code,
+            modifiers: KeyModifiers::NONE,
+            kind,
+            state: KeyEventState::NONE,
+        }
+    }
+
+    #[cfg(feature = "terminal")]
+    #[test]
+    fn terminal_keys() {
+        let press = |code| key_event(code, KeyEventKind::Press);
+        let mut keys = TerminalKeys::new(KeyMap::default(), false);
+
+        assert_eq!(keys.handle_key(press(KeyCode::Char('Q'))), None);
+        assert_eq!(keys.handle_key(press(KeyCode::Backspace)), None);
+        for _ in 0..10 {
+            let frame = keys.frame(None);
+            assert_eq!(frame.keys[0x4], 1);
+            assert!(frame.rewinding);
+        }
+        // No release is reported, so the key lets go on its own
+        assert_eq!(keys.frame(None), InputFrame::default());
+
+        assert_eq!(keys.handle_key(press(KeyCode::Esc)), Some(KeyStroke::Quit));
+        assert_eq!(
+            keys.handle_key(press(KeyCode::Char(' '))),
+            Some(KeyStroke::Next)
+        );
+        assert_eq!(
+            keys.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(KeyStroke::Quit)
+        );
+        assert_eq!(
+            keys.handle_key(press(KeyCode::F(9))),
+            Some(KeyStroke::SaveState)
+        );
+
+        let mut keys = TerminalKeys::new(KeyMap::default(), true);
+        keys.handle_key(press(KeyCode::Char('x')));
+        for _ in 0..30 {
+            assert_eq!(keys.frame(None).keys[0x0], 1);
+        }
+        keys.handle_key(key_event(KeyCode::Char('x'), KeyEventKind::Release));
+        assert_eq!(keys.frame(None).keys[0x0], 0);
+        assert_eq!(
+            keys.handle_key(key_event(KeyCode::Esc, KeyEventKind::Release)),
+            None
+        );
+    }
+
+    #[cfg(feature = "terminal")]
+    #[test]
+    fn terminal_key_names() {
+        let press = |code| key_event(code, KeyEventKind::Press);
+        let held = |layout: &str, codes: &[KeyCode]| {
+            let mut keys = TerminalKeys::new(KeyMap::preset(layout).unwrap(), false);
+            for &code in codes {
+                keys.handle_key(press(code));
+            }
+            let frame = keys.frame(None).keys;
+            (0..16).filter(|&key| frame[key] == 1).collect::<Vec<_>>()
+        };
+
+        // The AZERTY "é" is named after the US "2" in its place
+        let azerty = [KeyCode::Char('&'), KeyCode::Char('é'), KeyCode::Char('"')];
+        assert_eq!(held("azerty", &azerty), [0x1, 0x2, 0x3]);
+        assert_eq!(held("qwerty", &[KeyCode::Char('W')]), [0x5]);
+        assert_eq!(held("arrows", &[KeyCode::Left, KeyCode::Right]), [0x7, 0x9]);
+        assert_eq!(held("qwerty", &[KeyCode::Char('!')]), []);
+
+        for layout in LAYOUTS {
+            assert_eq!(
+                terminal::check_keymap(&KeyMap::preset(layout).unwrap()),
+                Ok(())
+            );
+        }
+        let keymap = KeyMap::from_config("5 = lshift w\n6 = kp6 Return", "a.ch8", None).unwrap();
+        assert_eq!(
+            terminal::check_keymap(&keymap),
+            Err(StartupError::TerminalKeys(vec![
+                String::from("kp6"),
+                String::from("lshift")
+            ]))
+        );
+    }
+
+    #[cfg(feature = "terminal")]
+    #[test]
+    fn parse_terminal_args() {
+        assert_eq!(
+            args(&["--terminal", "a.ch8"]),
+            Ok(Command::Run(Config {
+                terminal: Some(CellStyle::HalfBlocks),
+                headless: false,
+                ..Config::new("a.ch8")
+            }))
+        );
+        assert_eq!(
+            args(&["--braille", "--terminal", "a.ch8"]),
+            Ok(Command::Run(Config {
+                terminal: Some(CellStyle::Braille),
+                headless: false,
+                ..Config::new("a.ch8")
+            }))
+        );
+        assert_eq!(
+            args(&["--terminal", "--headless", "a.ch8"]),
+            Err(String::from(
+                "--terminal and --headless cannot be used together"
+            ))
+        );
+        assert_eq!(
+            args(&["--debug", "--terminal", "a.ch8"]),
+            Err(String::from(
+                "--debug reads stdin, which --terminal needs for keys"
+            ))
+        );
+        assert!(args(&["--terminal", "--until", "200", "a.ch8"]).is_err());
     }
 
     #[test]
@@ -0,0 +1,131 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::cpu::CPU;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TraceSettings {
+    /// File to write to, `-` for stdout.
+    pub path: Option<String>,
+    /// Only instructions at addresses in this range are traced.
+    pub range: Option<(u16, u16)>,
+    /// Keep only the last N lines, written out if the CPU faults.
+    pub last: Option<usize>,
+}
+
+impl TraceSettings {
+    pub fn enabled(&self) -> bool {
+        self.path.is_some() || self.range.is_some() || self.last.is_some()
+    }
+}
+
+/// Writes one line per instruction, with the machine state before it runs.
+pub struct Tracer {
+    output: Box<dyn Write>,
+    range: Option<(u16, u16)>,
+    /// Lines held back in ring buffer mode, and how many to keep.
+    ring: Option<(VecDeque<String>, usize)>,
+    /// Instructions seen, including those outside `range`.
+    cycle: u64,
+    failed: bool,
+}
+
+impl Tracer {
+    pub fn new(settings: &TraceSettings, output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            output,
+            range: settings.range,
+            ring: settings
+                .last
+                .map(|last| (VecDeque::with_capacity(last), last)),
+            cycle: 0,
+            failed: false,
+        }
+    }
+
+    /// Opens the trace file, stdout when there is none.
+    pub fn open(settings: &TraceSettings) -> io::Result<Tracer> {
+        let output: Box<dyn Write> = match settings.path.as_deref() {
+            None | Some("-") => Box::new(io::stdout()),
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        };
+
+        Ok(Tracer::new(settings, output))
+    }
+
+    /// Traces the instruction at PC, called just before it runs.
+    pub fn record(&mut self, cpu: &CPU) {
+        self.cycle += 1;
+        if let Some((start, end)) = self.range {
+            if !(start..=end).contains(&cpu.pc) {
+                return;
+            }
+        }
+
+        let line = trace_line(self.cycle, cpu);
+        match &mut self.ring {
+            Some((lines, last)) => {
+                if lines.len() == *last {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            None => self.write(&line),
+        }
+    }
+
+    /// Writes out the ring buffer after a fault.
+    pub fn dump(&mut self) {
+        if let Some((lines, _)) = self.ring.take() {
+            for line in lines {
+                self.write(&line);
+            }
+        }
+        if let Err(err) = self.output.flush() {
+            eprintln!("could not write trace: {}", err);
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.failed {
+            return;
+        }
+        if let Err(err) = writeln!(self.output, "{}", line) {
+            eprintln!("could not write trace: {}", err);
+            self.failed = true;
+        }
+    }
+}
+
+/// `PC:xxxx OP:xxxx V0:xx .. VF:xx I:xxxx SP:xx DT:xx ST:xx` in hex, the
+/// register order other CHIP-8 emulators log in, so their traces can be
+/// compared line by line with a plain `diff`. The cycle number and the
+/// mnemonic trail at the end, where they are easy to cut off.
+pub fn trace_line(cycle: u64, cpu: &CPU) -> String {
+    let opcode = u16::from_be_bytes([
+        cpu.ram[cpu.pc as usize],
+        cpu.ram[cpu.pc.wrapping_add(1) as usize],
+    ]);
+    let regs: Vec<String> = cpu
+        .regs
+        .iter()
+        .enumerate()
+        .map(|(x, reg)| format!("V{:X}:{:02X}", x, reg))
+        .collect();
+
+    format!(
+        "PC:{:04X} OP:{:04X} {} I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X} CY:{} {}",
+        cpu.pc,
+        opcode,
+        regs.join(" "),
+        cpu.index_reg,
+        cpu.sp,
+        cpu.delay_timer,
+        cpu.sound_timer,
+        cycle,
+        cpu.peek_instruction()
+    )
+}
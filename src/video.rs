@@ -0,0 +1,32 @@
+use crate::cpu::{Display, CPU};
+
+/// Shows the display, called once per loop whether or not the CPU ran.
+pub trait VideoSink {
+    /// `status` is a line such as a fault or the paused address, to show
+    /// alongside the display.
+    fn present(&mut self, cpu: &CPU, status: Option<&str>);
+
+    /// A one-off message such as a saved state or a debugger reply, which
+    /// may span several lines.
+    fn message(&mut self, text: &str);
+}
+
+/// Keeps every frame presented so it can be checked or written out.
+#[derive(Default)]
+pub struct MemoryVideo {
+    pub frames: Vec<Display>,
+    /// Status shown with the latest frame.
+    pub status: Option<String>,
+    pub messages: Vec<String>,
+}
+
+impl VideoSink for MemoryVideo {
+    fn present(&mut self, cpu: &CPU, status: Option<&str>) {
+        self.frames.push(cpu.display);
+        self.status = status.map(str::to_string);
+    }
+
+    fn message(&mut self, text: &str) {
+        self.messages.push(text.to_string());
+    }
+}
@@ -4,89 +4,122 @@ use sdl2::{
     render::{Canvas, TextureCreator},
     ttf::Font,
     video::{Window, WindowContext},
-    EventPump,
+    Sdl,
 };
 
-use crate::cpu::CPU;
+use crate::{
+    config::Config,
+    cpu::{Display, CPU},
+    error::StartupError,
+    video::VideoSink,
+};
+
+pub const EMBEDDED_FONT: &[u8] = include_bytes!("fonts/Raleway-Black.ttf");
 
-const TEXT_INDENT: i32 = 700;
+const INFO_PANEL_WIDTH: u32 = 860;
+const MIN_WINDOW_HEIGHT: u32 = 640;
 
 struct CpuInfo {
     text_height: i32,
-    coords: Rect
+    coords: Rect,
 }
 
-impl CpuInfo { 
-    fn init_cpu_info(y_coord: i32, text_height: i32) -> CpuInfo {
-        let coords = Rect::new(
-            TEXT_INDENT,
-            y_coord,
-            0 as u32,
-            text_height as u32);
-
-    CpuInfo { text_height, coords}
+impl CpuInfo {
+    fn init_cpu_info(x_coord: i32, y_coord: i32, text_height: i32) -> CpuInfo {
+        let coords = Rect::new(x_coord, y_coord, 0, text_height as u32);
 
-}
+        CpuInfo {
+            text_height,
+            coords,
+        }
+    }
 }
 
 pub struct WindowManager {
+    pub sdl_context: Sdl,
     pub canvas: Canvas<Window>,
-    pub event_pump: EventPump,
     pub texture_creator: TextureCreator<WindowContext>,
+    scale: u32,
+    colours: [Color; 4],
 }
 
 impl WindowManager {
-    pub fn init_sdl() -> WindowManager {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let event_pump = sdl_context.event_pump().unwrap();
+    pub fn init_sdl(config: &Config) -> Result<WindowManager, StartupError> {
+        let sdl_context = sdl2::init().map_err(StartupError::SdlInit)?;
+        let video_subsystem = sdl_context.video().map_err(StartupError::SdlInit)?;
+
+        let rom_name = std::path::Path::new(&config.rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let title = format!("RustEight - {} [{}]", rom_name, config.quirks.name());
 
         let window = video_subsystem
-            .window("RustEight", 1500, 640)
+            .window(
+                &title,
+                64 * config.scale + INFO_PANEL_WIDTH,
+                (32 * config.scale).max(MIN_WINDOW_HEIGHT),
+            )
             .position_centered()
             .build()
-            .unwrap();
+            .map_err(|err| StartupError::SdlInit(err.to_string()))?;
 
-        let canvas = window.into_canvas().build().unwrap();
+        let canvas = window
+            .into_canvas()
+            .build()
+            .map_err(|err| StartupError::SdlInit(err.to_string()))?;
 
         let texture_creator = canvas.texture_creator();
 
-        WindowManager {
+        let colours = [0, 1, 2, 3].map(|pixel| {
+            let (r, g, b) = config.palette.colour(pixel);
+            Color::RGB(r, g, b)
+        });
+
+        Ok(WindowManager {
+            sdl_context,
             canvas,
-            event_pump,
             texture_creator,
-        }
+            scale: config.scale,
+            colours,
+        })
     }
 
-    pub fn refresh(&mut self, display: &[[u8; 64]; 32], font: &Font, cpu: &CPU) {
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+    /// Draws the display and registers, with a status line such as a fault
+    /// below them.
+    pub fn refresh(&mut self, display: &Display, font: &Font, cpu: &CPU, status: Option<&str>) {
+        self.canvas.set_draw_color(self.colours[0]);
         self.canvas.clear();
-        self.canvas.set_draw_color(Color::RGB(0, 255, 0));
-
-        for x_coord in 0..64 {
-            for y_coord in 0..32 {
-                let byte = display[y_coord][x_coord];
-                for bit in 0..8 {
-                    // Draw a rect for every pixel that is on
-                    if (byte >> bit) & 1 == 1 {
-                        self.canvas
-                            .fill_rect(Rect::new(
-                                (x_coord as i32 + bit) * 10,
-                                y_coord as i32 * 10,
-                                10,
-                                10,
-                            ))
-                            .unwrap();
-                    }
+
+        // High resolution pixels are half the size so both modes fill the same area
+        let (width, height) = cpu.screen_size();
+        let pixels_per_scale = (width / 64) as i32;
+        let scale = self.scale as i32;
+        let edge = |coord: usize| coord as i32 * scale / pixels_per_scale;
+
+        for (y_coord, row) in display[..height].iter().enumerate() {
+            for (x_coord, pixel) in row[..width].iter().enumerate() {
+                // Draw a rect for every pixel that is on in any plane
+                if pixel & 0b11 != 0 {
+                    self.canvas
+                        .set_draw_color(self.colours[(pixel & 0b11) as usize]);
+                    self.canvas
+                        .fill_rect(Rect::new(
+                            edge(x_coord),
+                            edge(y_coord),
+                            (edge(x_coord + 1) - edge(x_coord)) as u32,
+                            (edge(y_coord + 1) - edge(y_coord)) as u32,
+                        ))
+                        .unwrap();
                 }
             }
         }
-        self.render_cpu_info(&font, cpu);
+        self.render_cpu_info(font, cpu, status);
         self.canvas.present();
     }
 
-    pub fn render_text(&mut self, rect: Rect, font: &Font, text: &str) {        
-        let surface = font.render(&text).blended(Color::RGB(200, 0, 0));
+    pub fn render_text(&mut self, rect: Rect, font: &Font, text: &str) {
+        let surface = font.render(text).blended(Color::RGB(200, 0, 0));
 
         let surface = match surface {
             Ok(surface) => surface,
@@ -101,20 +134,47 @@ impl WindowManager {
         self.canvas.copy(&texture, None, Some(rect)).unwrap();
     }
 
-    fn render_cpu_info(&mut self, font:&Font, cpu: &CPU) {
-        let mut cpu_info = CpuInfo::init_cpu_info(0, 30);
+    fn render_cpu_info(&mut self, font: &Font, cpu: &CPU, status: Option<&str>) {
+        let text_indent = 64 * self.scale as i32 + 60;
+        let mut cpu_info = CpuInfo::init_cpu_info(text_indent, 0, 30);
 
-        let reg_info = cpu.regs;
         let mut reg_str = String::with_capacity(10);
 
-        for i in 0..reg_info.len() {
+        for (i, reg) in cpu.regs.iter().enumerate() {
             reg_str.push_str(&i.to_string());
             reg_str.push_str(&String::from(" "));
-            reg_str.push_str(&reg_info[i].to_string());
+            reg_str.push_str(&reg.to_string());
             cpu_info.coords.set_width(reg_str.len() as u32 * 20);
             self.render_text(cpu_info.coords, font, &reg_str);
             cpu_info.coords.y += cpu_info.text_height;
             reg_str = String::from("Reg");
         }
+
+        if let Some(status) = status {
+            cpu_info.coords.set_width(status.len() as u32 * 20);
+            self.render_text(cpu_info.coords, font, status);
+        }
+    }
+}
+
+/// The window with the font its text is drawn in.
+pub struct WindowVideo<'ttf> {
+    window: WindowManager,
+    font: Font<'ttf, 'static>,
+}
+
+impl<'ttf> WindowVideo<'ttf> {
+    pub fn new(window: WindowManager, font: Font<'ttf, 'static>) -> WindowVideo<'ttf> {
+        WindowVideo { window, font }
+    }
+}
+
+impl VideoSink for WindowVideo<'_> {
+    fn present(&mut self, cpu: &CPU, status: Option<&str>) {
+        self.window.refresh(&cpu.display, &self.font, cpu, status);
+    }
+
+    fn message(&mut self, text: &str) {
+        println!("{}", text);
     }
 }
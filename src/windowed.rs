@@ -0,0 +1,378 @@
+use std::path::Path;
+
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    event::Event,
+    keyboard::{Keycode, Scancode},
+    rwops::RWops,
+    EventPump, Sdl,
+};
+
+use crate::{
+    audio::{AudioSettings, AudioSink, NullAudio, ToneGenerator, SAMPLE_RATE},
+    config::Config,
+    error::StartupError,
+    frontend::{self, Frontend},
+    keymap::KeyMap,
+    keypad::{InputFrame, InputSource, KeyStroke},
+    movie::Playback,
+    scheduler::SystemClock,
+    storage,
+    trace::Tracer,
+    Machine,
+};
+
+use crate::window_manager::{WindowManager, WindowVideo, EMBEDDED_FONT};
+
+/// Which hex keys are held down, kept across frames so a key stays pressed
+/// until its key-up event arrives.
+#[derive(Debug, Default)]
+pub struct KeypadState {
+    pub keys: [u8; 16],
+    /// The rewind hotkey is held rather than pressed.
+    pub rewinding: bool,
+    keymap: KeyMap,
+}
+
+impl KeypadState {
+    pub fn new(keymap: KeyMap) -> KeypadState {
+        KeypadState {
+            keys: [0u8; 16],
+            rewinding: false,
+            keymap,
+        }
+    }
+
+    /// Updates the held keys, returning the emulator hotkey the event
+    /// triggered, if any.
+    pub fn handle_event(&mut self, event: &Event) -> Option<KeyStroke> {
+        match event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => Some(KeyStroke::Quit),
+            Event::KeyDown {
+                keycode: Some(Keycode::Space | Keycode::F6),
+                ..
+            } => Some(KeyStroke::Next),
+            Event::KeyDown {
+                keycode: Some(Keycode::F1),
+                ..
+            } => Some(KeyStroke::Mute),
+            Event::KeyDown {
+                keycode: Some(Keycode::F5),
+                ..
+            } => Some(KeyStroke::Pause),
+            Event::KeyDown {
+                keycode: Some(Keycode::F7),
+                ..
+            } => Some(KeyStroke::StepOver),
+            Event::KeyDown {
+                keycode: Some(Keycode::F8),
+                ..
+            } => Some(KeyStroke::StepOut),
+            Event::KeyDown {
+                keycode: Some(Keycode::F9),
+                ..
+            } => Some(KeyStroke::SaveState),
+            Event::KeyDown {
+                keycode: Some(Keycode::F10),
+                ..
+            } => Some(KeyStroke::LoadState),
+            Event::KeyDown {
+                keycode: Some(Keycode::F11),
+                ..
+            } => Some(KeyStroke::NextSlot),
+            Event::KeyDown {
+                keycode: Some(Keycode::Backspace),
+                ..
+            } => {
+                self.rewinding = true;
+                None
+            }
+            Event::KeyUp {
+                keycode: Some(Keycode::Backspace),
+                ..
+            } => {
+                self.rewinding = false;
+                None
+            }
+            Event::KeyDown {
+                keycode, scancode, ..
+            } => {
+                if let Some(key) = self.hex_key(*keycode, *scancode) {
+                    self.keys[key] = 1;
+                }
+                None
+            }
+            Event::KeyUp {
+                keycode, scancode, ..
+            } => {
+                if let Some(key) = self.hex_key(*keycode, *scancode) {
+                    self.keys[key] = 0;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn hex_key(&self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<usize> {
+        let name = keycode
+            .and_then(keycode_name)
+            .or_else(|| scancode.and_then(scancode_name))?;
+
+        self.keymap.hex_key(name)
+    }
+}
+
+/// Key names used by key map files. SDL's own names need the library
+/// loaded, so the table lives here.
+fn keycode_name(keycode: Keycode) -> Option<&'static str> {
+    let name = match keycode {
+        Keycode::Num0 => "0",
+        Keycode::Num1 => "1",
+        Keycode::Num2 => "2",
+        Keycode::Num3 => "3",
+        Keycode::Num4 => "4",
+        Keycode::Num5 => "5",
+        Keycode::Num6 => "6",
+        Keycode::Num7 => "7",
+        Keycode::Num8 => "8",
+        Keycode::Num9 => "9",
+        Keycode::A => "a",
+        Keycode::B => "b",
+        Keycode::C => "c",
+        Keycode::D => "d",
+        Keycode::E => "e",
+        Keycode::F => "f",
+        Keycode::G => "g",
+        Keycode::H => "h",
+        Keycode::I => "i",
+        Keycode::J => "j",
+        Keycode::K => "k",
+        Keycode::L => "l",
+        Keycode::M => "m",
+        Keycode::N => "n",
+        Keycode::O => "o",
+        Keycode::P => "p",
+        Keycode::Q => "q",
+        Keycode::R => "r",
+        Keycode::S => "s",
+        Keycode::T => "t",
+        Keycode::U => "u",
+        Keycode::V => "v",
+        Keycode::W => "w",
+        Keycode::X => "x",
+        Keycode::Y => "y",
+        Keycode::Z => "z",
+        Keycode::Up => "up",
+        Keycode::Down => "down",
+        Keycode::Left => "left",
+        Keycode::Right => "right",
+        Keycode::Return => "return",
+        Keycode::Tab => "tab",
+        Keycode::Backspace => "backspace",
+        Keycode::LShift => "lshift",
+        Keycode::RShift => "rshift",
+        Keycode::LCtrl => "lctrl",
+        Keycode::RCtrl => "rctrl",
+        Keycode::Kp0 => "kp0",
+        Keycode::Kp1 => "kp1",
+        Keycode::Kp2 => "kp2",
+        Keycode::Kp3 => "kp3",
+        Keycode::Kp4 => "kp4",
+        Keycode::Kp5 => "kp5",
+        Keycode::Kp6 => "kp6",
+        Keycode::Kp7 => "kp7",
+        Keycode::Kp8 => "kp8",
+        Keycode::Kp9 => "kp9",
+        Keycode::KpPlus => "kp+",
+        Keycode::KpMinus => "kp-",
+        Keycode::KpMultiply => "kp*",
+        Keycode::KpDivide => "kp/",
+        Keycode::KpPeriod => "kp.",
+        Keycode::KpEnter => "kpenter",
+        Keycode::Quote => "'",
+        Keycode::Quotedbl => "\"",
+        Keycode::Ampersand => "&",
+        Keycode::Comma => ",",
+        Keycode::Period => ".",
+        Keycode::Slash => "/",
+        Keycode::Semicolon => ";",
+        Keycode::Minus => "-",
+        Keycode::Equals => "=",
+        Keycode::LeftBracket => "[",
+        Keycode::RightBracket => "]",
+        Keycode::Backslash => "\\",
+        Keycode::Backquote => "`",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Name of the key in the same place on a US keyboard, for keys with no
+/// SDL keycode such as accented letters.
+fn scancode_name(scancode: Scancode) -> Option<&'static str> {
+    let name = match scancode {
+        Scancode::Num0 => "0",
+        Scancode::Num1 => "1",
+        Scancode::Num2 => "2",
+        Scancode::Num3 => "3",
+        Scancode::Num4 => "4",
+        Scancode::Num5 => "5",
+        Scancode::Num6 => "6",
+        Scancode::Num7 => "7",
+        Scancode::Num8 => "8",
+        Scancode::Num9 => "9",
+        Scancode::Apostrophe => "'",
+        Scancode::Comma => ",",
+        Scancode::Period => ".",
+        Scancode::Slash => "/",
+        Scancode::Semicolon => ";",
+        Scancode::Minus => "-",
+        Scancode::Equals => "=",
+        Scancode::LeftBracket => "[",
+        Scancode::RightBracket => "]",
+        Scancode::Backslash => "\\",
+        Scancode::Grave => "`",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// The keyboard of an SDL window.
+pub struct SdlInput {
+    event_pump: EventPump,
+    keypad: KeypadState,
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump, keymap: KeyMap) -> SdlInput {
+        SdlInput {
+            event_pump,
+            keypad: KeypadState::new(keymap),
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, _frame: u64) -> InputFrame {
+        let mut hotkey = None;
+
+        for event in self.event_pump.poll_iter() {
+            let stroke = self.keypad.handle_event(&event);
+            if hotkey.is_none() {
+                hotkey = stroke;
+            }
+        }
+
+        InputFrame {
+            keys: self.keypad.keys,
+            hotkey,
+            rewinding: self.keypad.rewinding,
+        }
+    }
+}
+
+struct SdlTone {
+    generator: ToneGenerator,
+    playing: bool,
+}
+
+impl AudioCallback for SdlTone {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.playing {
+                self.generator.next_sample()
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+pub struct SdlAudio {
+    device: AudioDevice<SdlTone>,
+}
+
+impl SdlAudio {
+    pub fn init_audio(sdl_context: &Sdl, settings: AudioSettings) -> Result<SdlAudio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| SdlTone {
+            generator: ToneGenerator::new(settings, spec.freq as u32),
+            playing: false,
+        })?;
+        device.resume();
+
+        Ok(SdlAudio { device })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn update(&mut self, playing: bool) {
+        self.device.lock().playing = playing;
+    }
+
+    fn toggle_mute(&mut self) {
+        self.device.lock().generator.toggle_mute();
+    }
+}
+
+/// Runs the ROM in an SDL window with the keyboard and audio device as its
+/// front end.
+pub fn run(
+    machine: &mut Machine,
+    config: &Config,
+    flags_path: &Path,
+    tracer: Option<Tracer>,
+    playback: Option<Playback>,
+) -> Result<(), StartupError> {
+    let ttf_context = sdl2::ttf::init().map_err(|err| StartupError::SdlInit(err.to_string()))?;
+    let font = RWops::from_bytes(EMBEDDED_FONT)
+        .and_then(|rwops| ttf_context.load_font_from_rwops(rwops, 128))
+        .map_err(StartupError::FontUnreadable)?;
+
+    let window = WindowManager::init_sdl(config)?;
+    let event_pump = window
+        .sdl_context
+        .event_pump()
+        .map_err(StartupError::SdlInit)?;
+    let mut input = SdlInput::new(event_pump, storage::load_keymap(config));
+    let mut audio: Box<dyn AudioSink> =
+        match SdlAudio::init_audio(&window.sdl_context, config.audio) {
+            Ok(audio) => Box::new(audio),
+            Err(err) => {
+                eprintln!("could not open audio device: {}", err);
+                Box::new(NullAudio)
+            }
+        };
+    let mut video = WindowVideo::new(window, font);
+
+    frontend::run(
+        machine,
+        SystemClock::new(),
+        config,
+        flags_path,
+        tracer,
+        playback,
+        Frontend {
+            video: &mut video,
+            input: &mut input,
+            audio: audio.as_mut(),
+        },
+    );
+
+    Ok(())
+}